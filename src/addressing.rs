@@ -0,0 +1,32 @@
+pub trait Addressing {
+    fn encode(base: isize, address: isize) -> isize;
+    fn decode(base: isize, value: isize) -> isize;
+}
+
+pub enum Absolute {}
+
+impl Addressing for Absolute {
+    #[inline(always)]
+    fn encode(_: isize, address: isize) -> isize {
+        address
+    }
+
+    #[inline(always)]
+    fn decode(_: isize, value: isize) -> isize {
+        value
+    }
+}
+
+pub enum Relative {}
+
+impl Addressing for Relative {
+    #[inline(always)]
+    fn encode(base: isize, address: isize) -> isize {
+        address.wrapping_sub(base)
+    }
+
+    #[inline(always)]
+    fn decode(base: isize, value: isize) -> isize {
+        value.wrapping_add(base)
+    }
+}
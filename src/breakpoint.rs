@@ -0,0 +1,462 @@
+use crate::{
+    decode,
+    info::{ENABLED, INSTALLED},
+    near, protect,
+    signal::{self, Chain, REG_IP},
+    util, Error, FnPtr, HookInfo, Patch,
+};
+
+#[cfg(feature = "registry")]
+use crate::registry;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use libc::{c_int, c_void, siginfo_t, SIGTRAP, SI_KERNEL};
+
+const MAX_BREAKPOINTS: usize = 64;
+
+struct Slot {
+    target: AtomicUsize,
+    detour: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Slot = Slot {
+    target: AtomicUsize::new(0),
+    detour: AtomicUsize::new(0),
+};
+
+static SLOTS: [Slot; MAX_BREAKPOINTS] = [EMPTY; MAX_BREAKPOINTS];
+
+static CHAIN: Chain = Chain::new();
+
+unsafe extern "C" fn handler(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    if (*info).si_code != SI_KERNEL {
+        if CHAIN.forward(signal, info, context) {
+            libc::raise(signal);
+        }
+
+        return;
+    }
+
+    let ip = &mut signal::registers(context)[REG_IP as usize];
+    let address = (*ip as usize).wrapping_sub(1);
+
+    for slot in &SLOTS {
+        if slot.target.load(Ordering::Acquire) == address {
+            *ip = slot.detour.load(Ordering::Acquire) as _;
+            return;
+        }
+    }
+
+    if CHAIN.forward(signal, info, context) {
+        signal::registers(context)[REG_IP as usize] = address as _;
+    }
+}
+
+#[cfg(feature = "registry")]
+unsafe fn restore(info: &HookInfo, enabled: bool) -> Result<(), Error> {
+    let byte = if enabled {
+        info.patched[0]
+    } else {
+        info.original[0]
+    };
+    util::patch(info.target as *mut u8, &[byte])
+}
+
+pub struct Hook<T: 'static> {
+    detour: T,
+    target: usize,
+    trampoline: usize,
+    slot: usize,
+    original: u8,
+    stolen_len: u8,
+    state: u8,
+    #[cfg(feature = "registry")]
+    id: usize,
+}
+
+impl<T> Hook<T> {
+    pub const unsafe fn new(detour: T) -> Self {
+        Self {
+            detour,
+            target: 0,
+            trampoline: 0,
+            slot: 0,
+            original: 0,
+            stolen_len: 0,
+            state: 0,
+            #[cfg(feature = "registry")]
+            id: 0,
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.state & INSTALLED != 0
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state & ENABLED != 0
+    }
+}
+
+impl<T: FnPtr> Hook<T> {
+    pub unsafe fn try_set_detour(&mut self, detour: T) -> Result<(), Error> {
+        self.guarded(|hook, _| {
+            if hook.is_installed() {
+                SLOTS[hook.slot]
+                    .detour
+                    .store(detour.to_addr(), Ordering::Release);
+            }
+
+            hook.detour = detour;
+
+            Ok(())
+        })
+    }
+
+    pub unsafe fn set_detour(&mut self, detour: T) {
+        self.try_set_detour(detour).unwrap()
+    }
+
+    pub unsafe fn try_hook(&mut self, target: T) -> Result<(), Error> {
+        #[cfg(feature = "registry")]
+        registry::check()?;
+
+        if self.is_installed() {
+            return Err(Error::AlreadyInstalled);
+        }
+
+        CHAIN.install(SIGTRAP, handler)?;
+
+        let target = target.to_addr();
+
+        let trampoline =
+            near::allocate(target, protect::page_size()).ok_or(Error::AllocationFailed)?;
+
+        let len = match decode::relocate(target, trampoline) {
+            Ok(len) => len,
+            Err(error) => {
+                near::free(trampoline, protect::page_size());
+                return Err(error);
+            }
+        };
+
+        let slot = match SLOTS.iter().position(|slot| {
+            slot.target
+                .compare_exchange(0, usize::MAX, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        }) {
+            Some(slot) => slot,
+            None => {
+                near::free(trampoline, protect::page_size());
+                return Err(Error::AllocationFailed);
+            }
+        };
+
+        SLOTS[slot]
+            .detour
+            .store(self.detour.to_addr(), Ordering::Release);
+        SLOTS[slot].target.store(target, Ordering::Release);
+
+        self.target = target;
+        self.trampoline = trampoline;
+        self.slot = slot;
+        self.original = *(target as *const u8);
+        self.stolen_len = len as u8;
+        self.state = INSTALLED;
+
+        #[cfg(feature = "registry")]
+        match registry::join(self.info().unwrap(), false, restore) {
+            Ok(id) => self.id = id,
+            Err(error) => {
+                self.try_unhook()?;
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn hook(&mut self, target: T) {
+        self.try_hook(target).unwrap()
+    }
+
+    pub unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        SLOTS[self.slot].target.store(0, Ordering::Release);
+        near::free(self.trampoline, protect::page_size());
+
+        self.trampoline = 0;
+        self.state = 0;
+
+        #[cfg(feature = "registry")]
+        registry::leave(self.id);
+
+        Ok(())
+    }
+
+    pub unsafe fn unhook(&mut self) {
+        self.try_unhook().unwrap()
+    }
+
+    pub unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        self.guarded(|hook, killed| {
+            if killed && !hook.is_enabled() {
+                return Err(Error::Killed);
+            }
+
+            let byte = if hook.is_enabled() {
+                hook.original
+            } else {
+                0xCC
+            };
+            util::patch(hook.target as *mut u8, &[byte])?;
+
+            hook.state ^= ENABLED;
+
+            Ok(())
+        })
+    }
+
+    pub unsafe fn toggle(&mut self) {
+        self.try_toggle().unwrap()
+    }
+
+    pub unsafe fn try_enable(&mut self) -> Result<(), Error> {
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn enable(&mut self) {
+        self.try_enable().unwrap()
+    }
+
+    pub unsafe fn try_disable(&mut self) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Err(Error::Disabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn disable(&mut self) {
+        self.try_disable().unwrap()
+    }
+
+    pub unsafe fn target(&self) -> T {
+        T::from_addr(self.target)
+    }
+
+    pub unsafe fn trampoline(&self) -> T {
+        T::from_addr(self.trampoline)
+    }
+
+    pub unsafe fn info(&self) -> Option<HookInfo> {
+        if !self.is_installed() {
+            return None;
+        }
+
+        let mut original = *(self.target as *const [u8; 5]);
+        original[0] = self.original;
+
+        let mut patched = original;
+        patched[0] = 0xCC;
+
+        Some(HookInfo {
+            target: self.target,
+            detour: self.detour.to_addr(),
+            trampoline: self.trampoline,
+            trampoline_len: self.stolen_len as usize,
+            original,
+            patched,
+            stolen: 1,
+        })
+    }
+
+    #[cfg(feature = "registry")]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        registry::guard(|guard| {
+            let result = f(self, guard.killed);
+
+            if let Some(info) = self.info() {
+                guard.update(self.id, info, self.is_enabled());
+            }
+
+            result
+        })
+    }
+
+    #[cfg(not(feature = "registry"))]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        f(self, false)
+    }
+}
+
+impl<T: FnPtr> Patch for Hook<T> {
+    type Target = T;
+
+    fn is_installed(&self) -> bool {
+        Hook::is_installed(self)
+    }
+
+    fn is_enabled(&self) -> bool {
+        Hook::is_enabled(self)
+    }
+
+    unsafe fn info(&self) -> Option<HookInfo> {
+        Hook::info(self)
+    }
+
+    unsafe fn try_hook(&mut self, target: T) -> Result<(), Error> {
+        Hook::try_hook(self, target)
+    }
+
+    unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        Hook::try_unhook(self)
+    }
+
+    unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        Hook::try_toggle(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    core::arch::global_asm!(
+        ".pushsection .text",
+        ".p2align 4",
+        "ezhook_breakpoint_zero:",
+        "xor eax, eax",
+        "ret",
+        ".p2align 4",
+        "ezhook_breakpoint_jump:",
+        "jmp 2f",
+        "ud2",
+        "2:",
+        "mov eax, 7",
+        "ret",
+        ".popsection",
+        ".pushsection .data.rel.ro, \"aw\"",
+        ".p2align 3",
+        ".globl ezhook_breakpoint_table",
+        ".hidden ezhook_breakpoint_table",
+        "ezhook_breakpoint_table:",
+        ".dc.a ezhook_breakpoint_zero",
+        ".dc.a ezhook_breakpoint_jump",
+        ".popsection",
+    );
+
+    extern "C" {
+        static ezhook_breakpoint_table: [extern "C" fn() -> i32; 2];
+    }
+
+    extern "C" fn one() -> i32 {
+        1
+    }
+
+    extern "C" fn two() -> i32 {
+        2
+    }
+
+    #[test]
+    fn breakpoint() {
+        let [zero, jump] = unsafe { ezhook_breakpoint_table };
+
+        crate::util::unprotect(zero as usize, 2);
+        crate::util::unprotect(jump as usize, 2);
+
+        let mut first = unsafe { Hook::<extern "C" fn() -> i32>::new(one) };
+        let mut second = unsafe { Hook::<extern "C" fn() -> i32>::new(two) };
+
+        for _ in 0..2 {
+            unsafe { first.hook(zero) };
+            unsafe { second.hook(jump) };
+
+            assert_eq!(zero(), 0);
+            assert_eq!(jump(), 7);
+
+            unsafe { first.enable() };
+            unsafe { second.enable() };
+
+            assert_eq!(unsafe { first.info() }.unwrap().patched[0], 0xCC);
+            assert_eq!(zero(), 1);
+            assert_eq!(jump(), 2);
+            assert_eq!(unsafe { first.trampoline() }(), 0);
+            assert_eq!(unsafe { second.trampoline() }(), 7);
+
+            unsafe { first.set_detour(two) };
+
+            assert_eq!(zero(), 2);
+
+            unsafe { first.disable() };
+            unsafe { second.disable() };
+
+            assert_eq!(zero(), 0);
+            assert_eq!(jump(), 7);
+
+            unsafe { first.set_detour(one) };
+            unsafe { first.unhook() };
+            unsafe { second.unhook() };
+        }
+    }
+
+    #[test]
+    fn foreign_trap() {
+        use core::{mem, ptr};
+        use libc::{sigaction, sigemptyset, SA_SIGINFO};
+
+        static FOREIGN: AtomicUsize = AtomicUsize::new(0);
+
+        extern "C" fn foreign(_: c_int, _: *mut siginfo_t, _: *mut c_void) {
+            FOREIGN.fetch_add(1, Ordering::SeqCst);
+        }
+
+        if !crate::util::isolated("breakpoint::tests::foreign_trap") {
+            return;
+        }
+
+        let mut action: sigaction = unsafe { mem::zeroed() };
+        action.sa_sigaction = foreign as *const () as usize;
+        action.sa_flags = SA_SIGINFO;
+        unsafe { sigemptyset(&mut action.sa_mask) };
+        assert_eq!(unsafe { sigaction(SIGTRAP, &action, ptr::null_mut()) }, 0);
+
+        let [zero, _] = unsafe { ezhook_breakpoint_table };
+        crate::util::unprotect(zero as usize, 2);
+
+        let mut hook = unsafe { Hook::<extern "C" fn() -> i32>::new(one) };
+        unsafe { hook.hook(zero) };
+        unsafe { hook.enable() };
+
+        assert_eq!(unsafe { libc::raise(SIGTRAP) }, 0);
+        assert_eq!(FOREIGN.load(Ordering::SeqCst), 1);
+        assert_eq!(zero(), 1);
+        assert_eq!(FOREIGN.load(Ordering::SeqCst), 1);
+
+        unsafe { hook.disable() };
+        unsafe { hook.unhook() };
+    }
+}
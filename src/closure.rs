@@ -0,0 +1,324 @@
+use crate::{near, protect, Error, FnPtr};
+
+use core::{arch::global_asm, cell::Cell};
+use std::{boxed::Box, ptr};
+
+const DEPTH: usize = 16;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Cell<usize> = Cell::new(0);
+
+std::thread_local! {
+    static CONTEXTS: [Cell<usize>; DEPTH] = const { [EMPTY; DEPTH] };
+    static TOP: Cell<usize> = const { Cell::new(0) };
+}
+
+extern "C" {
+    fn __ezhook_closure_enter();
+}
+
+unsafe extern "C" fn push(context: usize) {
+    let top = TOP.with(Cell::get);
+
+    if top == DEPTH {
+        std::process::abort();
+    }
+
+    CONTEXTS.with(|contexts| contexts[top].set(context));
+    TOP.with(|cell| cell.set(top + 1));
+}
+
+fn pop() -> usize {
+    let top = TOP.with(Cell::get) - 1;
+    TOP.with(|cell| cell.set(top));
+    CONTEXTS.with(|contexts| contexts[top].get())
+}
+
+#[cfg(target_arch = "x86_64")]
+global_asm!(
+    ".pushsection .text",
+    ".p2align 4",
+    ".globl __ezhook_closure_enter",
+    ".hidden __ezhook_closure_enter",
+    "__ezhook_closure_enter:",
+    "push rdi",
+    "push rsi",
+    "push rdx",
+    "push rcx",
+    "push r8",
+    "push r9",
+    "push rax",
+    "push r10",
+    "push r11",
+    "sub rsp, 128",
+    "movdqu [rsp], xmm0",
+    "movdqu [rsp + 16], xmm1",
+    "movdqu [rsp + 32], xmm2",
+    "movdqu [rsp + 48], xmm3",
+    "movdqu [rsp + 64], xmm4",
+    "movdqu [rsp + 80], xmm5",
+    "movdqu [rsp + 96], xmm6",
+    "movdqu [rsp + 112], xmm7",
+    "mov rdi, r11",
+    "call {push}",
+    "movdqu xmm0, [rsp]",
+    "movdqu xmm1, [rsp + 16]",
+    "movdqu xmm2, [rsp + 32]",
+    "movdqu xmm3, [rsp + 48]",
+    "movdqu xmm4, [rsp + 64]",
+    "movdqu xmm5, [rsp + 80]",
+    "movdqu xmm6, [rsp + 96]",
+    "movdqu xmm7, [rsp + 112]",
+    "add rsp, 128",
+    "pop r11",
+    "pop r10",
+    "pop rax",
+    "pop r9",
+    "pop r8",
+    "pop rcx",
+    "pop rdx",
+    "pop rsi",
+    "pop rdi",
+    "jmp r10",
+    ".popsection",
+    push = sym push,
+);
+
+#[cfg(target_arch = "x86")]
+global_asm!(
+    ".pushsection .text",
+    ".p2align 4",
+    ".globl __ezhook_closure_enter",
+    ".hidden __ezhook_closure_enter",
+    "__ezhook_closure_enter:",
+    "push eax",
+    "push ecx",
+    "push edx",
+    "push dword ptr [esp + 12]",
+    "call {push}",
+    "add esp, 4",
+    "pop edx",
+    "pop ecx",
+    "pop eax",
+    "add esp, 4",
+    "ret",
+    ".popsection",
+    push = sym push,
+);
+
+pub trait Closure<F>: FnPtr {
+    fn shim() -> Self;
+}
+
+macro_rules! impl_closure {
+    (@abi $abi:literal [$($arg:ident),*]) => {
+        impl_closure!(@impl $abi [$($arg),*] extern $abi fn($($arg),*) -> Ret);
+        impl_closure!(@impl $abi [$($arg),*] unsafe extern $abi fn($($arg),*) -> Ret);
+    };
+
+    (@impl $abi:literal [$($arg:ident),*] $ty:ty) => {
+        impl<Func, Ret: 'static, $($arg: 'static),*> Closure<Func> for $ty
+        where
+            Func: Fn($($arg),*) -> Ret + Send + Sync + 'static,
+        {
+            fn shim() -> Self {
+                #[allow(non_snake_case, clippy::too_many_arguments)]
+                #[inline(never)]
+                fn call<Func: Fn($($arg),*) -> Ret, Ret, $($arg),*>(
+                    context: usize,
+                    $($arg: $arg),*
+                ) -> Ret {
+                    let closure = unsafe { &*(context as *const Func) };
+                    closure($($arg),*)
+                }
+
+                #[allow(non_snake_case, clippy::too_many_arguments)]
+                extern $abi fn shim<Func: Fn($($arg),*) -> Ret, Ret, $($arg),*>(
+                    $($arg: $arg),*
+                ) -> Ret {
+                    call::<Func, Ret, $($arg),*>(pop(), $($arg),*)
+                }
+
+                shim::<Func, Ret, $($arg),*>
+            }
+        }
+    };
+
+    ($($arg:ident),*) => {
+        impl_closure!(@abi "Rust" [$($arg),*]);
+        impl_closure!(@abi "C" [$($arg),*]);
+        impl_closure!(@abi "system" [$($arg),*]);
+
+        #[cfg(target_arch = "x86")]
+        impl_closure!(@abi "cdecl" [$($arg),*]);
+        #[cfg(target_arch = "x86")]
+        impl_closure!(@abi "stdcall" [$($arg),*]);
+        #[cfg(target_arch = "x86")]
+        impl_closure!(@abi "fastcall" [$($arg),*]);
+
+        #[cfg(target_arch = "x86_64")]
+        impl_closure!(@abi "sysv64" [$($arg),*]);
+        #[cfg(target_arch = "x86_64")]
+        impl_closure!(@abi "win64" [$($arg),*]);
+    };
+}
+
+impl_closure!();
+impl_closure!(A);
+impl_closure!(A, B);
+impl_closure!(A, B, C);
+impl_closure!(A, B, C, D);
+impl_closure!(A, B, C, D, E);
+impl_closure!(A, B, C, D, E, F);
+impl_closure!(A, B, C, D, E, F, G);
+impl_closure!(A, B, C, D, E, F, G, H);
+impl_closure!(A, B, C, D, E, F, G, H, I);
+impl_closure!(A, B, C, D, E, F, G, H, I, J);
+impl_closure!(A, B, C, D, E, F, G, H, I, J, K);
+impl_closure!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+const THUNK: usize = 32;
+
+unsafe fn drop_context<F>(context: usize) {
+    drop(Box::from_raw(context as *mut F));
+}
+
+pub(crate) struct Owned {
+    memory: usize,
+    context: usize,
+    drop: unsafe fn(usize),
+}
+
+impl Owned {
+    pub(crate) unsafe fn new<T: Closure<F>, F>(near: usize, closure: F) -> Result<Self, Error> {
+        let memory = near::allocate(near, protect::page_size()).ok_or(Error::AllocationFailed)?;
+        let context = Box::into_raw(Box::new(closure)) as usize;
+
+        let shim = T::shim().to_addr();
+        let thunk = (memory + THUNK) as *mut u8;
+
+        #[cfg(target_arch = "x86_64")]
+        let len = {
+            let mut code = [0u8; 20];
+            code[0..2].copy_from_slice(&[0x49, 0xBB]);
+            code[2..10].copy_from_slice(&(context as u64).to_ne_bytes());
+            code[10..12].copy_from_slice(&[0x49, 0xBA]);
+            code[12..20].copy_from_slice(&(shim as u64).to_ne_bytes());
+            ptr::copy_nonoverlapping(code.as_ptr(), thunk, code.len());
+            code.len()
+        };
+
+        #[cfg(target_arch = "x86")]
+        let len = {
+            let mut code = [0u8; 10];
+            code[0] = 0x68;
+            code[1..5].copy_from_slice(&(shim as u32).to_ne_bytes());
+            code[5] = 0x68;
+            code[6..10].copy_from_slice(&(context as u32).to_ne_bytes());
+            ptr::copy_nonoverlapping(code.as_ptr(), thunk, code.len());
+            code.len()
+        };
+
+        near::jump(
+            thunk as usize + len,
+            __ezhook_closure_enter as *const () as usize,
+        );
+
+        Ok(Self {
+            memory,
+            context,
+            drop: drop_context::<F>,
+        })
+    }
+
+    pub(crate) fn thunk(&self) -> usize {
+        self.memory + THUNK
+    }
+
+    pub(crate) unsafe fn trampoline(&self) -> &'static mut [u8; 24] {
+        &mut *(self.memory as *mut [u8; 24])
+    }
+
+    pub(crate) unsafe fn free(self) {
+        (self.drop)(self.context);
+        near::free(self.memory, protect::page_size());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{local::trampoline::Hook, util};
+
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[inline(never)]
+    fn square(x: i32) -> i32 {
+        util::black_box(x * x)
+    }
+
+    #[inline(never)]
+    #[allow(clippy::too_many_arguments)]
+    extern "C" fn mix(a: i64, b: f64, c: i32, d: f32, e: u8, f: i64, g: i64, h: i64) -> f64 {
+        util::black_box(a as f64 + b + c as f64 + d as f64 + e as f64 + (f + g + h) as f64)
+    }
+
+    #[test]
+    fn closure() {
+        util::unprotect(square as _, 5);
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let offset = 3;
+
+        let mut hook = unsafe {
+            Hook::<fn(i32) -> i32>::with_closure(square, move |x| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                x + offset
+            })
+        }
+        .unwrap();
+
+        assert_eq!(square(4), 16);
+
+        unsafe { hook.enable() };
+
+        assert_eq!(square(4), 7);
+        assert_eq!(square(5), 8);
+        assert_eq!(unsafe { hook.trampoline() }(4), 16);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        unsafe { hook.disable() };
+        unsafe { hook.unhook() };
+
+        assert_eq!(square(4), 16);
+        assert_eq!(Arc::strong_count(&calls), 1);
+    }
+
+    #[test]
+    fn arguments() {
+        type Mix = extern "C" fn(i64, f64, i32, f32, u8, i64, i64, i64) -> f64;
+
+        util::unprotect(mix as _, 5);
+
+        let mut hook = unsafe {
+            Hook::<Mix>::with_closure(mix, |a, b, c, d, e, f, g, h| {
+                -(a as f64 + b + c as f64 + d as f64 + e as f64 + (f + g + h) as f64)
+            })
+        }
+        .unwrap();
+
+        unsafe { hook.enable() };
+
+        assert_eq!(mix(1, 2.5, 3, 4.5, 5, 6, 7, 8), -37.0);
+        assert_eq!(
+            unsafe { hook.trampoline() }(1, 2.5, 3, 4.5, 5, 6, 7, 8),
+            37.0
+        );
+
+        unsafe { hook.disable() };
+        unsafe { hook.unhook() };
+
+        assert_eq!(mix(1, 2.5, 3, 4.5, 5, 6, 7, 8), 37.0);
+    }
+}
@@ -0,0 +1,172 @@
+#[cfg(target_arch = "x86")]
+use lde::X86;
+
+#[cfg(target_arch = "x86_64")]
+use lde::X64 as X86;
+
+#[cfg(all(any(feature = "breakpoint", feature = "page"), target_os = "linux"))]
+use crate::near;
+
+#[cfg(any(
+    feature = "trampoline",
+    all(any(feature = "breakpoint", feature = "page"), target_os = "linux")
+))]
+use crate::Error;
+
+#[cfg(any(
+    feature = "trampoline",
+    all(any(feature = "breakpoint", feature = "page"), target_os = "linux")
+))]
+use core::ptr;
+
+#[cfg(feature = "trampoline")]
+use core::convert::TryFrom;
+
+pub(crate) fn length(code: &[u8]) -> usize {
+    X86.ld(code) as usize
+}
+
+#[cfg(any(
+    feature = "trampoline",
+    all(
+        any(feature = "trace", feature = "breakpoint", feature = "page"),
+        target_os = "linux"
+    )
+))]
+fn displacement(instruction: &[u8]) -> Option<Option<usize>> {
+    let mut index = 0;
+
+    while let Some(&byte) = instruction.get(index) {
+        match byte {
+            0x26 | 0x2E | 0x36 | 0x3E | 0x64 | 0x65 | 0x66 | 0x67 | 0xF0 | 0xF2 | 0xF3 => {}
+            #[cfg(target_arch = "x86_64")]
+            0x40..=0x4F => {}
+            _ => break,
+        }
+
+        index += 1;
+    }
+
+    let opcode = match instruction.get(index) {
+        Some(&opcode) => opcode,
+        None => return None,
+    };
+
+    let modrm = match opcode {
+        0x70..=0x7F | 0xC2 | 0xC3 | 0xCA | 0xCB | 0xCC | 0xE0..=0xE3 | 0xE8 | 0xE9 | 0xEB => {
+            return None
+        }
+        0xC4 | 0xC5 | 0x62 if cfg!(target_arch = "x86_64") => return None,
+        0x0F => {
+            let opcode = match instruction.get(index + 1) {
+                Some(&opcode) => opcode,
+                None => return None,
+            };
+
+            match opcode {
+                0x80..=0x8F => return None,
+                0x38 | 0x3A => Some(index + 3),
+                0x05..=0x0B
+                | 0x0E
+                | 0x30..=0x37
+                | 0x77
+                | 0xA0..=0xA2
+                | 0xA8..=0xAA
+                | 0xC8..=0xCF => None,
+                _ => Some(index + 2),
+            }
+        }
+        0x00..=0x3F if opcode & 0x04 == 0 => Some(index + 1),
+        0x62
+        | 0x63
+        | 0x69
+        | 0x6B
+        | 0x80..=0x8F
+        | 0xC0
+        | 0xC1
+        | 0xC4..=0xC7
+        | 0xD0..=0xD3
+        | 0xD8..=0xDF
+        | 0xF6
+        | 0xF7
+        | 0xFE
+        | 0xFF => Some(index + 1),
+        _ => None,
+    };
+
+    match modrm.and_then(|modrm| Some((modrm, *instruction.get(modrm)?))) {
+        Some((modrm, byte)) if cfg!(target_arch = "x86_64") && byte & 0xC7 == 0x05 => {
+            Some(Some(modrm + 1))
+        }
+        _ => Some(None),
+    }
+}
+
+#[cfg(all(
+    any(feature = "trace", feature = "breakpoint", feature = "page"),
+    target_os = "linux"
+))]
+pub(crate) fn position_independent(instruction: &[u8]) -> bool {
+    displacement(instruction) == Some(None)
+}
+
+#[cfg(all(any(feature = "breakpoint", feature = "page"), target_os = "linux"))]
+pub(crate) unsafe fn relocate(target: usize, trampoline: usize) -> Result<usize, Error> {
+    let code = &*(target as *const [u8; 15]);
+    let len = length(code);
+
+    let destination = match code[0] {
+        0xEB => Some((target + 2).wrapping_add(code[1] as i8 as usize)),
+        0xE9 => Some(
+            (target + 5)
+                .wrapping_add(ptr::read_unaligned(code[1..].as_ptr() as *const i32) as usize),
+        ),
+        _ => None,
+    };
+
+    match destination {
+        Some(destination) => {
+            near::jump(trampoline, destination);
+        }
+        None if len != 0 && position_independent(&code[..len]) => {
+            ptr::copy_nonoverlapping(code.as_ptr(), trampoline as *mut u8, len);
+            near::jump(trampoline + len, target + len);
+        }
+        None => return Err(Error::Unrelocatable),
+    }
+
+    Ok(len)
+}
+
+#[cfg(feature = "trampoline")]
+pub(crate) unsafe fn copy(source: usize, destination: usize) -> Result<(usize, usize), Error> {
+    let mut len = 0;
+    let mut count = 0;
+
+    while len < 5 {
+        let from = source + len;
+        let to = destination + len;
+        let code = &*(from as *const [u8; 15]);
+        let size = length(code);
+
+        let offset = match displacement(&code[..size]) {
+            Some(offset) if size != 0 => offset,
+            _ => return Err(Error::Unrelocatable),
+        };
+
+        ptr::copy_nonoverlapping(code.as_ptr(), to as *mut u8, size);
+
+        if let Some(offset) = offset {
+            let displacement = ptr::read_unaligned(code[offset..].as_ptr() as *const i32);
+            let displacement = i32::try_from(displacement as isize + from as isize - to as isize)
+                .map_err(|_| Error::Unrelocatable)?;
+
+            ptr::write_unaligned((to + offset) as *mut i32, displacement);
+        }
+
+        len += size;
+        count += 1;
+    }
+
+    Ok((len, count))
+}
@@ -0,0 +1,432 @@
+use crate::{
+    local::trampoline::Hook, module::Module, preload, scan::Pattern, symbol, Error, FnPtr,
+};
+
+use core::{
+    cell::UnsafeCell,
+    hint, ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::{cell::Cell, sync::Once};
+
+use libc::{c_char, c_int, c_void, dlinfo, RTLD_DEFAULT, RTLD_DI_LINKMAP};
+
+const MAX_DEFERRED: usize = 64;
+
+type Dlopen = unsafe extern "C" fn(*const c_char, c_int) -> *mut c_void;
+type Dlclose = unsafe extern "C" fn(*mut c_void) -> c_int;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Symbol(&'static str),
+    Pattern(&'static str),
+}
+
+pub struct Entry {
+    pub module: &'static str,
+    pub target: Target,
+    pub install: unsafe fn(usize) -> Result<(), Error>,
+    pub uninstall: unsafe fn() -> Result<(), Error>,
+}
+
+impl Entry {
+    fn name(&self) -> &'static str {
+        match self.target {
+            Target::Symbol(name) | Target::Pattern(name) => name,
+        }
+    }
+}
+
+struct Slot {
+    entry: Option<Entry>,
+    base: Option<usize>,
+    applied: bool,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Slot = Slot {
+    entry: None,
+    base: None,
+    applied: false,
+};
+
+struct Registry {
+    lock: AtomicBool,
+    slots: UnsafeCell<[Slot; MAX_DEFERRED]>,
+}
+
+unsafe impl Sync for Registry {}
+
+static REGISTRY: Registry = Registry {
+    lock: AtomicBool::new(false),
+    slots: UnsafeCell::new([EMPTY; MAX_DEFERRED]),
+};
+
+static mut DLOPEN: Hook<Dlopen> = unsafe { Hook::new(dlopen) };
+static mut DLCLOSE: Hook<Dlclose> = unsafe { Hook::new(dlclose) };
+
+std::thread_local! {
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+unsafe fn locked<R>(f: impl FnOnce(&mut [Slot; MAX_DEFERRED]) -> R) -> Option<R> {
+    if ACTIVE.with(|active| active.replace(true)) {
+        return None;
+    }
+
+    while REGISTRY
+        .lock
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        hint::spin_loop();
+    }
+
+    let result = f(&mut *REGISTRY.slots.get());
+
+    REGISTRY.lock.store(false, Ordering::Release);
+    ACTIVE.with(|active| active.set(false));
+
+    Some(result)
+}
+
+unsafe fn install(entry: &Entry, module: &Module) -> Result<(), Error> {
+    let target = match entry.target {
+        Target::Symbol(name) => symbol::resolve(entry.module, name).ok_or(Error::SymbolNotFound)?,
+        Target::Pattern(signature) => {
+            let pattern = Pattern::new(signature)?;
+
+            let mut found = None;
+            pattern.scan(module, |address| {
+                found = Some(address);
+                true
+            });
+
+            found.ok_or(Error::PatternNotFound)?
+        }
+    };
+
+    preload::unprotect(target)?;
+    (entry.install)(target)
+}
+
+unsafe fn apply(slot: &mut Slot) -> Result<(), Error> {
+    let entry = match &slot.entry {
+        Some(entry) if slot.base.is_none() => entry,
+        _ => return Ok(()),
+    };
+
+    let module = match Module::find(entry.module) {
+        Some(module) => module,
+        None => return Ok(()),
+    };
+
+    install(entry, &module)?;
+    slot.base = Some(module.base());
+    slot.applied = true;
+
+    Ok(())
+}
+
+unsafe fn apply_all(slots: &mut [Slot; MAX_DEFERRED]) {
+    for slot in slots.iter_mut() {
+        if let Err(error) = apply(slot) {
+            preload::report("hook", slot.entry.as_ref().unwrap().name(), error);
+        }
+    }
+}
+
+unsafe fn teardown(slot: &mut Slot) -> Result<(), Error> {
+    slot.base = None;
+
+    if slot.applied {
+        slot.applied = false;
+        (slot.entry.as_ref().unwrap().uninstall)()?;
+    }
+
+    Ok(())
+}
+
+unsafe fn teardown_all(slots: &mut [Slot; MAX_DEFERRED], base: usize) {
+    for slot in slots.iter_mut() {
+        if slot.base == Some(base) {
+            if let Err(error) = teardown(slot) {
+                preload::report("unhook", slot.entry.as_ref().unwrap().name(), error);
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void {
+    let original = (*ptr::addr_of!(DLOPEN)).trampoline();
+    let handle = original(filename, flags);
+
+    if !handle.is_null() {
+        locked(|slots| apply_all(slots));
+    }
+
+    handle
+}
+
+unsafe extern "C" fn dlclose(handle: *mut c_void) -> c_int {
+    let original = (*ptr::addr_of!(DLCLOSE)).trampoline();
+
+    let mut map: *const usize = ptr::null();
+    if dlinfo(handle, RTLD_DI_LINKMAP, &mut map as *mut _ as *mut c_void) != 0 || map.is_null() {
+        return original(handle);
+    }
+
+    let base = *map;
+
+    if locked(|slots| teardown_all(slots, base)).is_none() {
+        return original(handle);
+    }
+
+    let result = original(handle);
+
+    locked(|slots| apply_all(slots));
+
+    result
+}
+
+unsafe fn redirect<T: FnPtr>(hook: &mut Hook<T>, name: &[u8]) -> Result<(), Error> {
+    let target = libc::dlsym(RTLD_DEFAULT, name.as_ptr() as _) as usize;
+    if target == 0 {
+        return Err(Error::SymbolNotFound);
+    }
+
+    preload::unprotect(target)?;
+
+    let memory = hook.try_hook_relayed(T::from_addr(target))?;
+
+    if let Err(error) = hook.try_enable() {
+        let _ = hook.try_unhook();
+        hook.free_relayed(memory);
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+unsafe fn hook_loader() -> Result<(), Error> {
+    static LOADER: Once = Once::new();
+    static mut RESULT: Result<(), Error> = Ok(());
+
+    LOADER.call_once(|| {
+        let result = redirect(&mut *ptr::addr_of_mut!(DLOPEN), b"dlopen\0")
+            .and_then(|_| redirect(&mut *ptr::addr_of_mut!(DLCLOSE), b"dlclose\0"));
+
+        *ptr::addr_of_mut!(RESULT) = result;
+    });
+
+    *ptr::addr_of!(RESULT)
+}
+
+pub unsafe fn register(entry: Entry) -> Result<usize, Error> {
+    hook_loader()?;
+
+    locked(|slots| {
+        let (id, slot) = slots
+            .iter_mut()
+            .enumerate()
+            .find(|(_, slot)| slot.entry.is_none())
+            .ok_or(Error::RegistryFull)?;
+
+        *slot = Slot {
+            entry: Some(entry),
+            base: None,
+            applied: false,
+        };
+
+        if let Err(error) = apply(slot) {
+            slot.entry = None;
+            slot.base = None;
+            return Err(error);
+        }
+
+        Ok(id)
+    })
+    .unwrap_or(Err(Error::ThreadInPatch))
+}
+
+pub unsafe fn unregister(id: usize) -> Result<(), Error> {
+    locked(|slots| {
+        let slot = slots.get_mut(id).ok_or(Error::NotInstalled)?;
+
+        if slot.entry.is_none() {
+            return Err(Error::NotInstalled);
+        }
+
+        teardown(slot)?;
+        slot.entry = None;
+
+        Ok(())
+    })
+    .unwrap_or(Err(Error::ThreadInPatch))
+}
+
+pub fn is_applied(id: usize) -> bool {
+    unsafe { locked(|slots| slots.get(id).is_some_and(|slot| slot.applied)) }.unwrap_or(false)
+}
+
+#[macro_export]
+macro_rules! deferred {
+    ($($module:ident)::+ => ($library:expr, $target:expr)) => {
+        $crate::deferred::register($crate::deferred::Entry {
+            module: $library,
+            target: $target,
+            install: $($module)::+::__ez_install,
+            uninstall: $($module)::+::__ez_uninstall,
+        })
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::sync::atomic::AtomicUsize;
+    use std::{boxed::Box, format, string::String, vec::Vec};
+
+    use libc::{dlopen, dlsym, RTLD_NOW};
+
+    static BY_SYMBOL: AtomicUsize = AtomicUsize::new(0);
+    static BY_PATTERN: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe fn record_symbol(target: usize) -> Result<(), Error> {
+        BY_SYMBOL.store(target, Ordering::SeqCst);
+        Ok(())
+    }
+
+    unsafe fn forget_symbol() -> Result<(), Error> {
+        BY_SYMBOL.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    unsafe fn record_pattern(target: usize) -> Result<(), Error> {
+        BY_PATTERN.store(target, Ordering::SeqCst);
+        Ok(())
+    }
+
+    unsafe fn forget_pattern() -> Result<(), Error> {
+        BY_PATTERN.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe fn record_retry(_: usize) -> Result<(), Error> {
+        if ATTEMPTS.fetch_add(1, Ordering::SeqCst) == 0 {
+            return Err(Error::Unrelocatable);
+        }
+
+        Ok(())
+    }
+
+    unsafe fn forget_retry() -> Result<(), Error> {
+        Ok(())
+    }
+
+    unsafe fn open() -> (*mut c_void, usize) {
+        let handle = dlopen(b"libz.so.1\0".as_ptr() as _, RTLD_NOW);
+        assert!(!handle.is_null());
+
+        let version = dlsym(handle, b"zlibVersion\0".as_ptr() as _) as usize;
+        assert_ne!(version, 0);
+
+        (handle, version)
+    }
+
+    #[test]
+    fn deferred() {
+        unsafe {
+            let symbol = register(Entry {
+                module: "libz",
+                target: Target::Symbol("zlibVersion"),
+                install: record_symbol,
+                uninstall: forget_symbol,
+            })
+            .unwrap();
+
+            assert!(!is_applied(symbol));
+            assert_eq!(BY_SYMBOL.load(Ordering::SeqCst), 0);
+
+            let (handle, version) = open();
+
+            assert!(is_applied(symbol));
+            assert_eq!(BY_SYMBOL.load(Ordering::SeqCst), version);
+
+            let code = std::slice::from_raw_parts(version as *const u8, 16);
+            let signature: &'static str = Box::leak(
+                code.iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+                    .into_boxed_str(),
+            );
+
+            let pattern = register(Entry {
+                module: "libz",
+                target: Target::Pattern(signature),
+                install: record_pattern,
+                uninstall: forget_pattern,
+            })
+            .unwrap();
+
+            assert_eq!(BY_PATTERN.load(Ordering::SeqCst), version);
+
+            assert_eq!(libc::dlclose(handle), 0);
+
+            if Module::find("libz").is_none() {
+                assert!(!is_applied(symbol));
+                assert_eq!(BY_SYMBOL.load(Ordering::SeqCst), 0);
+                assert_eq!(BY_PATTERN.load(Ordering::SeqCst), 0);
+            }
+
+            let (handle, version) = open();
+
+            assert_eq!(BY_SYMBOL.load(Ordering::SeqCst), version);
+            assert_eq!(BY_PATTERN.load(Ordering::SeqCst), version);
+
+            unregister(symbol).unwrap();
+            unregister(pattern).unwrap();
+
+            assert_eq!(BY_SYMBOL.load(Ordering::SeqCst), 0);
+            assert_eq!(unregister(symbol), Err(Error::NotInstalled));
+
+            libc::dlclose(handle);
+        }
+    }
+
+    #[test]
+    fn retry() {
+        if !crate::util::isolated("deferred::tests::retry") {
+            return;
+        }
+
+        unsafe {
+            let retry = register(Entry {
+                module: "libz",
+                target: Target::Symbol("zlibVersion"),
+                install: record_retry,
+                uninstall: forget_retry,
+            })
+            .unwrap();
+
+            let (first, _) = open();
+
+            assert_eq!(ATTEMPTS.load(Ordering::SeqCst), 1);
+            assert!(!is_applied(retry));
+
+            let (second, _) = open();
+
+            assert_eq!(ATTEMPTS.load(Ordering::SeqCst), 2);
+            assert!(is_applied(retry));
+
+            unregister(retry).unwrap();
+
+            libc::dlclose(second);
+            libc::dlclose(first);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    AlreadyInstalled,
+    NotInstalled,
+    Enabled,
+    Disabled,
+    NoTrampoline,
+    OutOfRange,
+    SymbolNotFound,
+    ModuleNotFound,
+    InvalidPattern,
+    PatternNotFound,
+    ProtectFailed,
+    FreezeFailed,
+    ThreadInPatch,
+    AllocationFailed,
+    Unrelocatable,
+    HandlerFailed,
+    InvalidCallSite,
+    DispatchFailed,
+    RegistryFull,
+    Killed,
+    Misaligned,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Error::AlreadyInstalled => "hook is already installed",
+            Error::NotInstalled => "hook is not installed",
+            Error::Enabled => "hook is enabled",
+            Error::Disabled => "hook is disabled",
+            Error::NoTrampoline => "hook has no trampoline",
+            Error::OutOfRange => "detour is out of range of target",
+            Error::SymbolNotFound => "symbol not found",
+            Error::ModuleNotFound => "module not found",
+            Error::InvalidPattern => "invalid pattern",
+            Error::PatternNotFound => "pattern not found",
+            Error::ProtectFailed => "failed to change memory protection",
+            Error::FreezeFailed => "failed to freeze threads",
+            Error::ThreadInPatch => "a thread is executing inside a patch",
+            Error::AllocationFailed => "failed to allocate memory near target",
+            Error::Unrelocatable => "instruction cannot be relocated",
+            Error::HandlerFailed => "failed to install signal handler",
+            Error::InvalidCallSite => "instruction is not a rel32 call or jump",
+            Error::DispatchFailed => "failed to enable syscall user dispatch",
+            Error::RegistryFull => "registry is full",
+            Error::Killed => "hooks are globally disabled",
+            Error::Misaligned => "patch cannot be written atomically",
+        })
+    }
+}
@@ -0,0 +1,167 @@
+use core::{
+    cell::Cell,
+    hint,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+const MAX_THREADS: usize = 64;
+
+std::thread_local! {
+    static CURRENT: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn current() -> usize {
+    CURRENT.with(|current| {
+        if current.get() == 0 {
+            current.set(unsafe { libc::gettid() as usize });
+        }
+
+        current.get()
+    })
+}
+
+pub struct Filter {
+    lock: AtomicBool,
+    threads: [AtomicUsize; MAX_THREADS],
+}
+
+impl Filter {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicUsize = AtomicUsize::new(0);
+
+    pub const fn new() -> Self {
+        Self {
+            lock: AtomicBool::new(false),
+            threads: [Self::EMPTY; MAX_THREADS],
+        }
+    }
+
+    fn locked<R>(&self, f: impl FnOnce() -> R) -> R {
+        while self
+            .lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+
+        let result = f();
+
+        self.lock.store(false, Ordering::Release);
+
+        result
+    }
+
+    pub fn add(&self, thread: usize) -> bool {
+        self.locked(|| {
+            if self.contains(thread) {
+                return true;
+            }
+
+            match self
+                .threads
+                .iter()
+                .find(|slot| slot.load(Ordering::Relaxed) == 0)
+            {
+                Some(slot) => {
+                    slot.store(thread, Ordering::Release);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    pub fn remove(&self, thread: usize) -> bool {
+        self.locked(|| {
+            match self
+                .threads
+                .iter()
+                .find(|slot| slot.load(Ordering::Relaxed) == thread)
+            {
+                Some(slot) => {
+                    slot.store(0, Ordering::Release);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    pub fn contains(&self, thread: usize) -> bool {
+        thread != 0
+            && self
+                .threads
+                .iter()
+                .any(|slot| slot.load(Ordering::Acquire) == thread)
+    }
+
+    pub fn clear(&self) {
+        self.locked(|| {
+            for slot in &self.threads {
+                slot.store(0, Ordering::Release);
+            }
+        })
+    }
+
+    pub fn add_current(&self) -> bool {
+        self.add(current())
+    }
+
+    pub fn remove_current(&self) -> bool {
+        self.remove(current())
+    }
+
+    pub fn contains_current(&self) -> bool {
+        self.contains(current())
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter() {
+        let filter = Filter::new();
+
+        assert!(!filter.contains_current());
+        assert!(filter.add_current());
+        assert!(filter.contains_current());
+        assert!(filter.add(1));
+        assert!(filter.contains(1));
+
+        assert!(filter.remove_current());
+        assert!(!filter.contains_current());
+        assert!(!filter.remove_current());
+
+        filter.clear();
+        assert!(!filter.contains(1));
+    }
+
+    #[test]
+    fn add_concurrent() {
+        use std::{thread, vec::Vec};
+
+        static FILTER: Filter = Filter::new();
+
+        for _ in 0..100 {
+            let threads = (0..4)
+                .map(|_| thread::spawn(|| FILTER.add(7)))
+                .collect::<Vec<_>>();
+
+            for thread in threads {
+                assert!(thread.join().unwrap());
+            }
+
+            assert!(FILTER.remove(7));
+            assert!(!FILTER.contains(7));
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use core::{
+    arch::global_asm,
+    convert::TryFrom,
+    ptr,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+const WORDS: usize = 4;
+const FRAMES: usize = WORDS * 64;
+const THUNK: usize = 16;
+
+pub(crate) const LEAKED: usize = 1 << (usize::BITS - 2);
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) const PREFIX: usize = 37;
+
+#[cfg(target_arch = "x86")]
+pub(crate) const PREFIX: usize = 21;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicUsize = AtomicUsize::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE: AtomicU64 = AtomicU64::new(0);
+
+static USED: [AtomicU64; WORDS] = [FREE; WORDS];
+static RETURNS: [AtomicUsize; FRAMES] = [EMPTY; FRAMES];
+static COUNTERS: [AtomicUsize; FRAMES] = [EMPTY; FRAMES];
+
+extern "C" {
+    fn __ezhook_flight_enter();
+    fn __ezhook_flight_thunks();
+}
+
+fn claim() -> Option<usize> {
+    for (index, used) in USED.iter().enumerate() {
+        let mut current = used.load(Ordering::Acquire);
+
+        while current != !0 {
+            let bit = (!current).trailing_zeros();
+
+            match used.compare_exchange_weak(
+                current,
+                current | 1 << bit,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(index * 64 + bit as usize),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    None
+}
+
+unsafe extern "C" fn enter(counter: &AtomicUsize, slot: *mut usize) {
+    match claim() {
+        Some(frame) => {
+            RETURNS[frame].store(*slot, Ordering::Release);
+            COUNTERS[frame].store(counter as *const _ as usize, Ordering::Release);
+            *slot = __ezhook_flight_thunks as *const () as usize + frame * THUNK;
+        }
+        None => {
+            counter.fetch_or(LEAKED, Ordering::SeqCst);
+        }
+    }
+}
+
+unsafe extern "C" fn leave(frame: usize) -> usize {
+    let ret = RETURNS[frame].load(Ordering::Acquire);
+    let counter = &*(COUNTERS[frame].load(Ordering::Acquire) as *const AtomicUsize);
+
+    USED[frame / 64].fetch_and(!(1 << (frame % 64)), Ordering::AcqRel);
+    counter.fetch_sub(1, Ordering::SeqCst);
+
+    ret
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) unsafe fn prefix(stub: usize, counter: usize) -> Option<()> {
+    let code = stub as *mut u8;
+    let disp = i32::try_from(counter as isize - (stub + 8) as isize).ok()?;
+
+    ptr::copy_nonoverlapping([0xF0, 0x48, 0xFF, 0x05].as_ptr(), code, 4);
+    ptr::write_unaligned(code.add(4) as *mut i32, disp);
+    ptr::copy_nonoverlapping([0x4C, 0x8D, 0x1D].as_ptr(), code.add(8), 3);
+    ptr::write_unaligned(code.add(11) as *mut i32, (PREFIX - 15) as i32);
+    ptr::copy_nonoverlapping([0xFF, 0x25, 0, 0, 0, 0].as_ptr(), code.add(15), 6);
+    ptr::write_unaligned(
+        code.add(21) as *mut usize,
+        __ezhook_flight_enter as *const () as usize,
+    );
+    ptr::write_unaligned(code.add(29) as *mut usize, counter);
+
+    Some(())
+}
+
+#[cfg(target_arch = "x86")]
+pub(crate) unsafe fn prefix(stub: usize, counter: usize) -> Option<()> {
+    let code = stub as *mut u8;
+    let enter = (__ezhook_flight_enter as *const () as usize).wrapping_sub(stub + 17);
+
+    ptr::copy_nonoverlapping([0xF0, 0xFF, 0x05].as_ptr(), code, 3);
+    ptr::write_unaligned(code.add(3) as *mut usize, counter);
+    *code.add(7) = 0x68;
+    ptr::write_unaligned(code.add(8) as *mut usize, stub + PREFIX);
+    *code.add(12) = 0xE9;
+    ptr::write_unaligned(code.add(13) as *mut usize, enter);
+    ptr::write_unaligned(code.add(17) as *mut usize, counter);
+
+    Some(())
+}
+
+#[cfg(target_arch = "x86_64")]
+global_asm!(
+    ".pushsection .text",
+    ".p2align 4",
+    ".globl __ezhook_flight_enter",
+    ".hidden __ezhook_flight_enter",
+    "__ezhook_flight_enter:",
+    "push rdi",
+    "push rsi",
+    "push rdx",
+    "push rcx",
+    "push r8",
+    "push r9",
+    "push rax",
+    "push r10",
+    "push r11",
+    "sub rsp, 128",
+    "movdqu [rsp], xmm0",
+    "movdqu [rsp + 16], xmm1",
+    "movdqu [rsp + 32], xmm2",
+    "movdqu [rsp + 48], xmm3",
+    "movdqu [rsp + 64], xmm4",
+    "movdqu [rsp + 80], xmm5",
+    "movdqu [rsp + 96], xmm6",
+    "movdqu [rsp + 112], xmm7",
+    "mov rdi, [r11 - 8]",
+    "lea rsi, [rsp + 200]",
+    "call {enter}",
+    "movdqu xmm0, [rsp]",
+    "movdqu xmm1, [rsp + 16]",
+    "movdqu xmm2, [rsp + 32]",
+    "movdqu xmm3, [rsp + 48]",
+    "movdqu xmm4, [rsp + 64]",
+    "movdqu xmm5, [rsp + 80]",
+    "movdqu xmm6, [rsp + 96]",
+    "movdqu xmm7, [rsp + 112]",
+    "add rsp, 128",
+    "pop r11",
+    "pop r10",
+    "pop rax",
+    "pop r9",
+    "pop r8",
+    "pop rcx",
+    "pop rdx",
+    "pop rsi",
+    "pop rdi",
+    "jmp r11",
+    "",
+    ".p2align 4",
+    "__ezhook_flight_exit:",
+    "push rax",
+    "push rdx",
+    "sub rsp, 40",
+    "movdqu [rsp + 8], xmm0",
+    "movdqu [rsp + 24], xmm1",
+    "mov rdi, [rsp + 56]",
+    "call {leave}",
+    "mov [rsp + 56], rax",
+    "movdqu xmm0, [rsp + 8]",
+    "movdqu xmm1, [rsp + 24]",
+    "add rsp, 40",
+    "pop rdx",
+    "pop rax",
+    "ret",
+    "",
+    ".p2align 4",
+    ".globl __ezhook_flight_thunks",
+    ".hidden __ezhook_flight_thunks",
+    "__ezhook_flight_thunks:",
+    ".set ezhook_flight_frame, 0",
+    ".rept {frames}",
+    ".byte 0x68",
+    ".long ezhook_flight_frame",
+    "jmp __ezhook_flight_exit",
+    ".p2align 4",
+    ".set ezhook_flight_frame, ezhook_flight_frame + 1",
+    ".endr",
+    ".popsection",
+    enter = sym enter,
+    leave = sym leave,
+    frames = const FRAMES,
+);
+
+#[cfg(target_arch = "x86")]
+global_asm!(
+    ".pushsection .text",
+    ".p2align 4",
+    ".globl __ezhook_flight_enter",
+    ".hidden __ezhook_flight_enter",
+    "__ezhook_flight_enter:",
+    "push eax",
+    "push ecx",
+    "push edx",
+    "lea eax, [esp + 16]",
+    "push eax",
+    "mov eax, [esp + 16]",
+    "push dword ptr [eax - 4]",
+    "call {enter}",
+    "add esp, 8",
+    "pop edx",
+    "pop ecx",
+    "pop eax",
+    "ret",
+    "",
+    ".p2align 4",
+    "__ezhook_flight_exit:",
+    "push eax",
+    "push edx",
+    "push dword ptr [esp + 8]",
+    "call {leave}",
+    "add esp, 4",
+    "mov [esp + 8], eax",
+    "pop edx",
+    "pop eax",
+    "ret",
+    "",
+    ".p2align 4",
+    ".globl __ezhook_flight_thunks",
+    ".hidden __ezhook_flight_thunks",
+    "__ezhook_flight_thunks:",
+    ".set ezhook_flight_frame, 0",
+    ".rept {frames}",
+    ".byte 0x68",
+    ".long ezhook_flight_frame",
+    "jmp __ezhook_flight_exit",
+    ".p2align 4",
+    ".set ezhook_flight_frame, ezhook_flight_frame + 1",
+    ".endr",
+    ".popsection",
+    enter = sym enter,
+    leave = sym leave,
+    frames = const FRAMES,
+);
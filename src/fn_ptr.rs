@@ -0,0 +1,93 @@
+use core::mem;
+
+mod private {
+    pub trait Sealed {}
+}
+
+pub trait FnPtr: Copy + private::Sealed + 'static {
+    fn to_addr(self) -> usize;
+
+    unsafe fn from_addr(address: usize) -> Self;
+}
+
+macro_rules! impl_fn_ptr {
+    (@abi $abi:literal [$($arg:ident),*]) => {
+        impl_fn_ptr!(@impl [$($arg),*] extern $abi fn($($arg),*) -> Ret);
+        impl_fn_ptr!(@impl [$($arg),*] unsafe extern $abi fn($($arg),*) -> Ret);
+    };
+
+    (@impl [$($arg:ident),*] $ty:ty) => {
+        impl<Ret: 'static, $($arg: 'static),*> private::Sealed for $ty {}
+
+        impl<Ret: 'static, $($arg: 'static),*> FnPtr for $ty {
+            #[inline(always)]
+            fn to_addr(self) -> usize {
+                self as usize
+            }
+
+            #[inline(always)]
+            unsafe fn from_addr(address: usize) -> Self {
+                mem::transmute::<usize, Self>(address)
+            }
+        }
+    };
+
+    ($($arg:ident),*) => {
+        impl_fn_ptr!(@abi "Rust" [$($arg),*]);
+        impl_fn_ptr!(@abi "C" [$($arg),*]);
+        impl_fn_ptr!(@abi "system" [$($arg),*]);
+
+        #[cfg(target_arch = "x86")]
+        impl_fn_ptr!(@abi "cdecl" [$($arg),*]);
+        #[cfg(target_arch = "x86")]
+        impl_fn_ptr!(@abi "stdcall" [$($arg),*]);
+        #[cfg(target_arch = "x86")]
+        impl_fn_ptr!(@abi "fastcall" [$($arg),*]);
+
+        #[cfg(target_arch = "x86_64")]
+        impl_fn_ptr!(@abi "sysv64" [$($arg),*]);
+        #[cfg(target_arch = "x86_64")]
+        impl_fn_ptr!(@abi "win64" [$($arg),*]);
+    };
+}
+
+impl_fn_ptr!();
+impl_fn_ptr!(A);
+impl_fn_ptr!(A, B);
+impl_fn_ptr!(A, B, C);
+impl_fn_ptr!(A, B, C, D);
+impl_fn_ptr!(A, B, C, D, E);
+impl_fn_ptr!(A, B, C, D, E, F);
+impl_fn_ptr!(A, B, C, D, E, F, G);
+impl_fn_ptr!(A, B, C, D, E, F, G, H);
+impl_fn_ptr!(A, B, C, D, E, F, G, H, I);
+impl_fn_ptr!(A, B, C, D, E, F, G, H, I, J);
+impl_fn_ptr!(A, B, C, D, E, F, G, H, I, J, K);
+impl_fn_ptr!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+#[cfg(test)]
+#[allow(clippy::fn_address_comparisons)]
+mod tests {
+    use super::*;
+
+    fn add(x: i32, y: i32) -> i32 {
+        x + y
+    }
+
+    unsafe extern "C" fn negate(x: i32) -> i32 {
+        -x
+    }
+
+    #[test]
+    fn round_trip() {
+        let add = add as fn(i32, i32) -> i32;
+        assert!(unsafe { <fn(i32, i32) -> i32>::from_addr(add.to_addr()) } == add);
+
+        let negate = negate as unsafe extern "C" fn(i32) -> i32;
+        let address = negate.to_addr();
+        assert_eq!(
+            unsafe { <unsafe extern "C" fn(i32) -> i32>::from_addr(address)(4) },
+            -4
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::Error;
+
+use core::{
+    hint, mem, ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use libc::{
+    c_int, c_void, closedir, getpid, opendir, readdir, sigaction, sigemptyset, siginfo_t, syscall,
+    ucontext_t, SYS_tgkill, SA_RESTART, SA_SIGINFO,
+};
+
+#[cfg(target_arch = "x86")]
+use libc::REG_EIP as REG_IP;
+
+#[cfg(target_arch = "x86_64")]
+use libc::REG_RIP as REG_IP;
+
+const MAX_THREADS: usize = 1024;
+const SPIN_LIMIT: usize = 1 << 28;
+
+static LOCK: AtomicBool = AtomicBool::new(false);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static RELEASE: AtomicBool = AtomicBool::new(false);
+static STOPPED: AtomicUsize = AtomicUsize::new(0);
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicUsize = AtomicUsize::new(0);
+static TIDS: [AtomicUsize; MAX_THREADS] = [EMPTY; MAX_THREADS];
+static IPS: [AtomicUsize; MAX_THREADS] = [EMPTY; MAX_THREADS];
+
+unsafe extern "C" fn handler(_: c_int, _: *mut siginfo_t, context: *mut c_void) {
+    ACTIVE.fetch_add(1, Ordering::SeqCst);
+
+    let tid = libc::gettid() as usize;
+    let claimed = TIDS.iter().position(|slot| {
+        slot.compare_exchange(tid, 0, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    });
+
+    if let Some(index) = claimed {
+        let context = &*(context as *const ucontext_t);
+        IPS[index].store(
+            context.uc_mcontext.gregs[REG_IP as usize] as usize,
+            Ordering::SeqCst,
+        );
+
+        STOPPED.fetch_add(1, Ordering::SeqCst);
+
+        while !RELEASE.load(Ordering::SeqCst) {
+            hint::spin_loop();
+        }
+    }
+
+    ACTIVE.fetch_sub(1, Ordering::SeqCst);
+}
+
+fn signal() -> c_int {
+    libc::SIGRTMIN() + 7
+}
+
+pub(crate) struct Frozen {
+    signaled: usize,
+    previous: Option<sigaction>,
+}
+
+impl Frozen {
+    pub(crate) fn ips(&self) -> impl Iterator<Item = usize> {
+        IPS[..self.signaled]
+            .iter()
+            .map(|ip| ip.load(Ordering::SeqCst))
+    }
+}
+
+impl Drop for Frozen {
+    fn drop(&mut self) {
+        for slot in &TIDS {
+            slot.store(0, Ordering::SeqCst);
+        }
+
+        RELEASE.store(true, Ordering::SeqCst);
+
+        while ACTIVE.load(Ordering::SeqCst) != 0 {
+            hint::spin_loop();
+        }
+
+        if let Some(previous) = &self.previous {
+            unsafe { sigaction(signal(), previous, ptr::null_mut()) };
+        }
+
+        LOCK.store(false, Ordering::Release);
+    }
+}
+
+unsafe fn threads(mut f: impl FnMut(c_int)) -> bool {
+    let dir = opendir(b"/proc/self/task\0".as_ptr() as _);
+    if dir.is_null() {
+        return false;
+    }
+
+    loop {
+        let entry = readdir(dir);
+        if entry.is_null() {
+            break;
+        }
+
+        let name = &(*entry).d_name;
+        let mut tid: c_int = 0;
+        let mut valid = name[0] != 0;
+
+        for &byte in name.iter().take_while(|&&byte| byte != 0) {
+            match (byte as u8 as char).to_digit(10) {
+                Some(digit) => tid = tid * 10 + digit as c_int,
+                None => valid = false,
+            }
+        }
+
+        if valid {
+            f(tid);
+        }
+    }
+
+    closedir(dir);
+    true
+}
+
+pub(crate) unsafe fn freeze() -> Result<Frozen, Error> {
+    while LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        hint::spin_loop();
+    }
+
+    RELEASE.store(false, Ordering::SeqCst);
+    STOPPED.store(0, Ordering::SeqCst);
+
+    let mut frozen = Frozen {
+        signaled: 0,
+        previous: None,
+    };
+
+    if !INSTALLED.load(Ordering::SeqCst) {
+        let mut action: sigaction = mem::zeroed();
+        action.sa_sigaction = handler as *const () as usize;
+        action.sa_flags = SA_SIGINFO | SA_RESTART;
+        sigemptyset(&mut action.sa_mask);
+
+        let mut previous: sigaction = mem::zeroed();
+        if sigaction(signal(), &action, &mut previous) != 0 {
+            return Err(Error::FreezeFailed);
+        }
+
+        frozen.previous = Some(previous);
+    }
+
+    let pid = getpid();
+    let current = libc::gettid();
+    let mut overflow = false;
+
+    let listed = threads(|tid| {
+        if tid == current {
+            return;
+        }
+
+        if frozen.signaled == MAX_THREADS {
+            overflow = true;
+            return;
+        }
+
+        let slot = &TIDS[frozen.signaled];
+        slot.store(tid as usize, Ordering::SeqCst);
+
+        if syscall(
+            SYS_tgkill,
+            pid as libc::c_long,
+            tid as libc::c_long,
+            signal(),
+        ) == 0
+        {
+            frozen.signaled += 1;
+            frozen.previous = None;
+            INSTALLED.store(true, Ordering::SeqCst);
+        } else {
+            slot.store(0, Ordering::SeqCst);
+        }
+    });
+
+    if !listed || overflow {
+        return Err(Error::FreezeFailed);
+    }
+
+    let mut spins = 0;
+    while STOPPED.load(Ordering::SeqCst) < frozen.signaled {
+        spins += 1;
+        if spins == SPIN_LIMIT {
+            return Err(Error::FreezeFailed);
+        }
+
+        hint::spin_loop();
+    }
+
+    Ok(frozen)
+}
@@ -0,0 +1,27 @@
+use std::{cell::Cell, thread::LocalKey};
+
+pub struct Guard {
+    key: &'static LocalKey<Cell<usize>>,
+    depth: usize,
+}
+
+impl Guard {
+    pub fn enter(key: &'static LocalKey<Cell<usize>>) -> Self {
+        let depth = key.with(|depth| {
+            depth.set(depth.get() + 1);
+            depth.get()
+        });
+
+        Self { key, depth }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.key.with(|depth| depth.set(depth.get() - 1));
+    }
+}
@@ -0,0 +1,13 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HookInfo {
+    pub target: usize,
+    pub detour: usize,
+    pub trampoline: usize,
+    pub trampoline_len: usize,
+    pub original: [u8; 5],
+    pub patched: [u8; 5],
+    pub stolen: usize,
+}
+
+pub(crate) const INSTALLED: u8 = 1;
+pub(crate) const ENABLED: u8 = 2;
@@ -1,7 +1,150 @@
 #![no_std]
 #![cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+extern crate std;
+
+mod addressing;
+mod error;
+mod fn_ptr;
+mod info;
+mod patch;
 mod util;
 
+#[cfg(any(
+    feature = "trampoline",
+    all(
+        any(
+            feature = "trace",
+            feature = "breakpoint",
+            feature = "page",
+            feature = "scan"
+        ),
+        target_os = "linux"
+    )
+))]
+mod decode;
+#[cfg(all(feature = "reclaim", target_os = "linux"))]
+mod flight;
+#[cfg(all(feature = "transaction", target_os = "linux"))]
+mod freeze;
+#[cfg(all(
+    any(
+        feature = "closure",
+        feature = "probe",
+        feature = "breakpoint",
+        feature = "page",
+        feature = "vdso",
+        feature = "deferred",
+        feature = "reclaim"
+    ),
+    target_os = "linux"
+))]
+mod near;
+#[cfg(all(feature = "reclaim", target_os = "linux"))]
+mod pool;
+#[cfg(all(
+    any(
+        feature = "transaction",
+        feature = "closure",
+        feature = "probe",
+        feature = "breakpoint",
+        feature = "page",
+        feature = "vdso",
+        feature = "deferred",
+        feature = "reclaim",
+        feature = "registry"
+    ),
+    target_os = "linux"
+))]
+mod protect;
+#[cfg(all(feature = "libc", target_os = "linux"))]
+mod signal;
+
 pub mod local;
 pub mod remote;
+pub mod thunk;
+
+#[cfg(all(feature = "breakpoint", target_os = "linux"))]
+pub mod breakpoint;
+
+#[cfg(all(feature = "closure", target_os = "linux"))]
+pub mod closure;
+
+#[cfg(all(feature = "deferred", target_os = "linux"))]
+pub mod deferred;
+
+#[cfg(all(feature = "filter", target_os = "linux"))]
+pub mod filter;
+
+#[cfg(feature = "std")]
+pub mod guard;
+
+#[cfg(all(feature = "module", target_os = "linux"))]
+pub mod module;
+
+#[cfg(all(feature = "page", target_os = "linux"))]
+pub mod page;
+
+#[cfg(all(feature = "preload", target_os = "linux"))]
+pub mod preload;
+
+#[cfg(all(feature = "probe", target_os = "linux"))]
+pub mod probe;
+
+#[cfg(feature = "registry")]
+pub mod registry;
+
+#[cfg(all(feature = "scan", target_os = "linux"))]
+pub mod scan;
+
+#[cfg(all(feature = "symbol", target_os = "linux"))]
+pub mod symbol;
+
+#[cfg(all(feature = "syscall", target_os = "linux", target_arch = "x86_64"))]
+pub mod syscall;
+
+#[cfg(all(feature = "trace", target_os = "linux"))]
+pub mod trace;
+
+#[cfg(all(feature = "transaction", target_os = "linux"))]
+pub mod transaction;
+
+#[cfg(all(feature = "vdso", target_os = "linux"))]
+pub mod vdso;
+
+#[cfg(not(all(feature = "symbol", target_os = "linux")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ez_hook_symbol {
+    () => {};
+}
+
+#[cfg(not(all(feature = "preload", target_os = "linux")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ez_preload {
+    () => {};
+}
+
+#[cfg(not(all(feature = "reclaim", target_os = "linux")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ez_reclaim {
+    () => {};
+}
+
+pub use addressing::{Absolute, Addressing, Relative};
+pub use error::Error;
+pub use fn_ptr::FnPtr;
+pub use info::HookInfo;
+pub use patch::Patch;
+
+#[cfg(feature = "std")]
+#[doc(hidden)]
+pub mod __private {
+    pub use std::{cell::Cell, thread_local};
+}
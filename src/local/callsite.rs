@@ -0,0 +1,492 @@
+use crate::{
+    info::{ENABLED, INSTALLED},
+    util, Absolute, Addressing, Error, FnPtr, HookInfo,
+};
+
+#[cfg(feature = "registry")]
+use crate::registry;
+
+use core::{convert::TryFrom, marker::PhantomData, ptr};
+
+#[cfg(target_arch = "x86")]
+use lde::X86;
+
+#[cfg(target_arch = "x86_64")]
+use lde::X64 as X86;
+
+pub struct Hook<T: 'static, A: Addressing = Absolute> {
+    detour: T,
+    site: isize,
+    callee: isize,
+    state: u8,
+    #[cfg(feature = "registry")]
+    id: usize,
+    addressing: PhantomData<A>,
+}
+
+impl<T, A: Addressing> Hook<T, A> {
+    pub const unsafe fn new(detour: T) -> Self {
+        Self {
+            detour,
+            site: 0,
+            callee: 0,
+            state: 0,
+            #[cfg(feature = "registry")]
+            id: 0,
+            addressing: PhantomData,
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.state & INSTALLED != 0
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state & ENABLED != 0
+    }
+
+    #[inline(always)]
+    fn base(&self) -> isize {
+        self as *const _ as isize
+    }
+}
+
+pub unsafe fn destination(site: usize) -> Option<usize> {
+    let code = &*(site as *const [u8; 5]);
+
+    if !matches!(code[0], 0xE8 | 0xE9) || X86.ld(code) != 5 {
+        return None;
+    }
+
+    let displacement = ptr::read_unaligned((site + 1) as *const i32);
+    Some((site + 5).wrapping_add(displacement as isize as usize))
+}
+
+impl<T: FnPtr, A: Addressing> Hook<T, A> {
+    unsafe fn displacement(&self, destination: isize) -> Result<i32, Error> {
+        let site = A::decode(self.base(), self.site);
+        i32::try_from(destination - site - 5).map_err(|_| Error::OutOfRange)
+    }
+
+    pub unsafe fn try_set_detour(&mut self, detour: T) -> Result<(), Error> {
+        self.guarded(|hook, killed| {
+            if hook.is_enabled() {
+                let offset = hook.displacement(detour.to_addr() as isize)?;
+                let site = A::decode(hook.base(), hook.site);
+
+                if !killed {
+                    util::patch((site + 1) as *mut u8, &offset.to_ne_bytes())?;
+                }
+            }
+
+            hook.detour = detour;
+
+            Ok(())
+        })
+    }
+
+    pub unsafe fn set_detour(&mut self, detour: T) {
+        self.try_set_detour(detour).unwrap()
+    }
+
+    pub unsafe fn try_hook(&mut self, site: usize) -> Result<(), Error> {
+        #[cfg(feature = "registry")]
+        registry::check()?;
+
+        if self.is_installed() {
+            return Err(Error::AlreadyInstalled);
+        }
+
+        let callee = destination(site).ok_or(Error::InvalidCallSite)?;
+
+        self.site = A::encode(self.base(), site as isize);
+        self.callee = A::encode(self.base(), callee as isize);
+
+        if let Err(error) = self.displacement(self.detour.to_addr() as isize) {
+            self.site = 0;
+            self.callee = 0;
+            return Err(error);
+        }
+
+        self.state = INSTALLED;
+
+        #[cfg(feature = "registry")]
+        match registry::join(self.info().unwrap(), false, util::restore) {
+            Ok(id) => self.id = id,
+            Err(error) => {
+                self.state = 0;
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn hook(&mut self, site: usize) {
+        self.try_hook(site).unwrap()
+    }
+
+    pub unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        self.state = 0;
+
+        #[cfg(feature = "registry")]
+        registry::leave(self.id);
+
+        Ok(())
+    }
+
+    pub unsafe fn unhook(&mut self) {
+        self.try_unhook().unwrap()
+    }
+
+    pub unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        self.guarded(|hook, killed| {
+            if killed && !hook.is_enabled() {
+                return Err(Error::Killed);
+            }
+
+            let destination = if hook.is_enabled() {
+                A::decode(hook.base(), hook.callee)
+            } else {
+                hook.detour.to_addr() as isize
+            };
+
+            let offset = hook.displacement(destination)?;
+            let site = A::decode(hook.base(), hook.site);
+
+            util::patch((site + 1) as *mut u8, &offset.to_ne_bytes())?;
+
+            hook.state ^= ENABLED;
+
+            Ok(())
+        })
+    }
+
+    pub unsafe fn toggle(&mut self) {
+        self.try_toggle().unwrap()
+    }
+
+    pub unsafe fn try_enable(&mut self) -> Result<(), Error> {
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn enable(&mut self) {
+        self.try_enable().unwrap()
+    }
+
+    pub unsafe fn try_disable(&mut self) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Err(Error::Disabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn disable(&mut self) {
+        self.try_disable().unwrap()
+    }
+
+    pub unsafe fn site(&self) -> usize {
+        A::decode(self.base(), self.site) as usize
+    }
+
+    #[inline(always)]
+    pub unsafe fn orig_inline(&self) -> T {
+        T::from_addr(A::decode(self.base(), self.callee) as usize)
+    }
+
+    pub unsafe fn orig(&self) -> T {
+        self.orig_inline()
+    }
+
+    pub unsafe fn info(&self) -> Option<HookInfo> {
+        if !self.is_installed() {
+            return None;
+        }
+
+        let site = A::decode(self.base(), self.site);
+        let callee = A::decode(self.base(), self.callee);
+        let detour = self.detour.to_addr() as isize;
+
+        let encode = |destination: isize| {
+            let mut bytes = *(site as *const [u8; 5]);
+            bytes[1..].copy_from_slice(&((destination - site - 5) as i32).to_ne_bytes());
+            bytes
+        };
+
+        Some(HookInfo {
+            target: site as usize,
+            detour: detour as usize,
+            trampoline: callee as usize,
+            trampoline_len: 0,
+            original: encode(callee),
+            patched: encode(detour),
+            stolen: 1,
+        })
+    }
+
+    #[cfg(feature = "registry")]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        registry::guard(|guard| {
+            let result = f(self, guard.killed);
+
+            if let Some(info) = self.info() {
+                guard.update(self.id, info, self.is_enabled());
+            }
+
+            result
+        })
+    }
+
+    #[cfg(not(feature = "registry"))]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        f(self, false)
+    }
+}
+
+#[macro_export]
+macro_rules! local_callsite_hook {
+    {
+        @dollar($dollar:tt)
+
+        $(#[$attr:meta])* $vis:vis
+        $(unsafe $($unsafe:lifetime)?)? $(extern $($abi:literal)?)?
+        fn $name:ident($($param:tt)*) $(-> $ret:ty)? $body:block
+    } => {
+        $vis mod $name {
+            mod __ez_hook {
+                #[allow(unused_imports)]
+                use super::super::*;
+
+                #[allow(unused_macros)]
+                macro_rules! toggle {
+                    () => {
+                        #[allow(unused_unsafe)]
+                        unsafe { super::toggle() }
+                    };
+                }
+
+                #[allow(unused_macros)]
+                macro_rules! orig {
+                    ($dollar($arg:tt)*) => {
+                        {
+                            #[allow(unused_unsafe)]
+                            let orig = unsafe {
+                                super::orig()
+                            };
+
+                            orig($dollar($arg)*)
+                        }
+                    };
+                }
+
+                $(#[$attr])* pub
+                $(unsafe $($unsafe)?)? $(extern $($abi)?)?
+                fn $name($($param)*) $(-> $ret)? $body
+            }
+
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[allow(non_camel_case_types)]
+            type __ez_Func =
+                $(unsafe $($unsafe)?)? $(extern $($abi)?)?
+                fn($($param)*) $(-> $ret)?
+            ;
+
+            #[allow(non_upper_case_globals)]
+            static mut __ez_HOOK: $crate::local::callsite::Hook<__ez_Func> = unsafe {
+                $crate::local::callsite::Hook::new(__ez_hook::$name)
+            };
+
+            #[allow(dead_code)]
+            pub unsafe fn hook(site: usize) {
+                __ez_HOOK.hook(site)
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn unhook() {
+                __ez_HOOK.unhook()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn toggle() {
+                __ez_HOOK.toggle()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn enable() {
+                __ez_HOOK.enable()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn disable() {
+                __ez_HOOK.disable()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn is_installed() -> bool {
+                __ez_HOOK.is_installed()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn is_enabled() -> bool {
+                __ez_HOOK.is_enabled()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn site() -> usize {
+                __ez_HOOK.site()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn orig() -> __ez_Func {
+                __ez_HOOK.orig()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn info() -> Option<$crate::HookInfo> {
+                __ez_HOOK.info()
+            }
+        }
+    };
+
+    ($($tt:tt)*) => { $crate::local_callsite_hook! { @dollar($) $($tt)* } };
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    core::arch::global_asm!(
+        ".pushsection .text",
+        ".p2align 4",
+        "ezhook_callsite_callee:",
+        "lea eax, [rdi + rdi]",
+        "ret",
+        ".p2align 4",
+        "ezhook_callsite_first:",
+        "push rcx",
+        "ezhook_callsite_first_site:",
+        "call ezhook_callsite_callee",
+        "pop rcx",
+        "ret",
+        ".p2align 4",
+        "ezhook_callsite_second:",
+        ".byte 0xE9",
+        ".long ezhook_callsite_callee - . - 4",
+        ".p2align 4",
+        "ezhook_callsite_third:",
+        ".byte 0xE9",
+        ".long ezhook_callsite_callee - . - 4",
+        ".p2align 4",
+        "ezhook_callsite_prefixed:",
+        ".byte 0x2E, 0xE8",
+        ".long ezhook_callsite_callee - . - 4",
+        ".popsection",
+        ".pushsection .data.rel.ro, \"aw\"",
+        ".p2align 3",
+        ".globl ezhook_callsite_table",
+        ".hidden ezhook_callsite_table",
+        "ezhook_callsite_table:",
+        ".quad ezhook_callsite_callee",
+        ".quad ezhook_callsite_first",
+        ".quad ezhook_callsite_second",
+        ".quad ezhook_callsite_third",
+        ".quad ezhook_callsite_first_site",
+        ".quad ezhook_callsite_prefixed",
+        ".popsection",
+    );
+
+    extern "C" {
+        static ezhook_callsite_table: [extern "C" fn(i32) -> i32; 6];
+    }
+
+    extern "C" fn negate(x: i32) -> i32 {
+        -x
+    }
+
+    local_callsite_hook! {
+        extern "C" fn add_one_before(x: i32) -> i32 {
+            orig!(x + 1)
+        }
+    }
+
+    #[test]
+    fn hook() {
+        let [callee, first, second, _, site, prefixed] = unsafe { ezhook_callsite_table };
+        let site = site as usize;
+
+        crate::util::unprotect(site, 5);
+
+        let mut hook = unsafe { Hook::<extern "C" fn(i32) -> i32>::new(negate) };
+
+        assert_eq!(
+            unsafe { hook.try_hook(site + 1) },
+            Err(Error::InvalidCallSite)
+        );
+        assert_eq!(unsafe { destination(site) }, Some(callee as usize));
+        assert_eq!(unsafe { destination(prefixed as usize) }, None);
+
+        for _ in 0..2 {
+            unsafe { hook.hook(site) };
+
+            assert_eq!(unsafe { hook.orig() } as usize, callee as usize);
+            assert_eq!(first(4), 8);
+
+            unsafe { hook.enable() };
+
+            assert_eq!(unsafe { destination(site) }, Some(negate as usize));
+            assert_eq!(first(4), -4);
+            assert_eq!(second(4), 8);
+            assert_eq!(unsafe { hook.orig() }(4), 8);
+
+            unsafe { hook.disable() };
+            unsafe { hook.unhook() };
+
+            assert_eq!(first(4), 8);
+        }
+    }
+
+    #[test]
+    fn hook_macro() {
+        let [_, _, _, third, _, _] = unsafe { ezhook_callsite_table };
+        let site = third as usize;
+
+        crate::util::unprotect(site, 5);
+
+        unsafe { add_one_before::hook(site) };
+        unsafe { add_one_before::enable() };
+
+        assert_eq!(third(4), 10);
+        assert_eq!(unsafe { add_one_before::site() }, site);
+        assert_eq!(unsafe { add_one_before::info() }.unwrap().original[0], 0xE9);
+
+        unsafe { add_one_before::disable() };
+        unsafe { add_one_before::unhook() };
+
+        assert_eq!(third(4), 8);
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "callsite")]
+pub mod callsite;
 pub mod swap;
 
 #[cfg(feature = "trampoline")]
@@ -1,71 +1,477 @@
-use crate::util;
-
-use core::convert::{TryFrom, TryInto};
-
-pub struct Hook<T: 'static> {
-    detour_target: T,
+use crate::{
+    info::{ENABLED, INSTALLED},
+    util, Absolute, Addressing, Error, FnPtr, HookInfo, Patch,
+};
+
+#[cfg(feature = "registry")]
+use crate::registry;
+
+use core::{arch::asm, convert::TryFrom, marker::PhantomData, sync::atomic::AtomicBool};
+
+pub struct Hook<T: 'static, A: Addressing = Absolute> {
+    detour: T,
+    target: isize,
+    state: u8,
+    #[cfg(feature = "registry")]
+    id: usize,
+    lock: AtomicBool,
     scratch: [u8; 5],
+    addressing: PhantomData<A>,
 }
 
-impl<T> Hook<T> {
+impl<T, A: Addressing> Hook<T, A> {
     pub const unsafe fn new(detour: T) -> Self {
         Self {
-            detour_target: detour,
+            detour,
+            target: 0,
+            state: 0,
+            #[cfg(feature = "registry")]
+            id: 0,
+            lock: AtomicBool::new(false),
             scratch: [0xE9, 0, 0, 0, 0],
+            addressing: PhantomData,
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.state & INSTALLED != 0
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state & ENABLED != 0
+    }
+
+    #[inline(always)]
+    fn base(&self) -> isize {
+        self as *const _ as isize
+    }
+
+    #[inline(always)]
+    pub fn lock_inline(&self) {
+        unsafe {
+            asm!(
+                "2:",
+                "mov {value}, 1",
+                "xchg byte ptr [{lock}], {value}",
+                "test {value}, {value}",
+                "jz 3f",
+                "pause",
+                "jmp 2b",
+                "3:",
+                lock = in(reg) &self.lock as *const AtomicBool,
+                value = out(reg_byte) _,
+            );
+        }
+    }
+
+    #[inline(always)]
+    pub fn unlock_inline(&self) {
+        unsafe {
+            asm!(
+                "mov byte ptr [{lock}], 0",
+                lock = in(reg) &self.lock as *const AtomicBool,
+            );
         }
     }
 }
 
-impl<T: Copy> Hook<T> {
+pub struct Orig<'a, T: FnPtr, A: Addressing> {
+    hook: &'a mut Hook<T, A>,
+}
+
+impl<'a, T: FnPtr, A: Addressing> Orig<'a, T, A> {
+    #[inline(always)]
+    pub unsafe fn target(&self) -> T {
+        self.hook.target_inline()
+    }
+}
+
+impl<'a, T: FnPtr, A: Addressing> Drop for Orig<'a, T, A> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        unsafe { self.hook.exchange_inline() };
+        self.hook.unlock_inline();
+    }
+}
+
+impl<T: FnPtr, A: Addressing> Hook<T, A> {
+    pub unsafe fn try_set_detour(&mut self, detour: T) -> Result<(), Error> {
+        self.lock_inline();
+
+        let result = self.guarded(|hook, killed| {
+            if hook.is_installed() {
+                let target = A::decode(hook.base(), hook.target);
+                let offset = i32::try_from(detour.to_addr() as isize - target - 5)
+                    .map_err(|_| Error::OutOfRange)?;
+
+                if !hook.is_enabled() {
+                    hook.scratch[1..].copy_from_slice(&offset.to_ne_bytes());
+                } else if !killed {
+                    util::patch((target + 1) as *mut u8, &offset.to_ne_bytes())?;
+                }
+            }
+
+            hook.detour = detour;
+
+            Ok(())
+        });
+
+        self.unlock_inline();
+
+        result
+    }
+
     pub unsafe fn set_detour(&mut self, detour: T) {
-        self.detour_target = detour;
+        self.try_set_detour(detour).unwrap()
     }
 
-    pub unsafe fn hook(&mut self, target: T) {
-        let detour: isize = util::transmute(self.detour_target);
-        let target: isize = util::transmute(target);
+    pub unsafe fn try_hook(&mut self, target: T) -> Result<(), Error> {
+        #[cfg(feature = "registry")]
+        registry::check()?;
 
-        let offset = i32::try_from(detour - target - 5).unwrap();
+        if self.is_installed() {
+            return Err(Error::AlreadyInstalled);
+        }
+
+        let detour = self.detour.to_addr() as isize;
+        let target = target.to_addr() as isize;
+
+        let offset = i32::try_from(detour - target - 5).map_err(|_| Error::OutOfRange)?;
         self.scratch[1..].copy_from_slice(&offset.to_ne_bytes());
 
-        self.detour_target = util::transmute(target - self as *mut _ as isize);
+        self.target = A::encode(self.base(), target);
+        self.state = INSTALLED;
+
+        #[cfg(feature = "registry")]
+        match registry::join(self.info().unwrap(), false, util::restore) {
+            Ok(id) => self.id = id,
+            Err(error) => {
+                self.state = 0;
+                return Err(error);
+            }
+        }
+
+        Ok(())
     }
 
-    pub unsafe fn unhook(&mut self) {
-        let offset = i32::from_ne_bytes(self.scratch[1..].try_into().unwrap()) as isize;
+    pub unsafe fn hook(&mut self, target: T) {
+        self.try_hook(target).unwrap()
+    }
 
-        let target: isize = util::transmute(self.detour_target);
-        self.detour_target = util::transmute(offset + target + self as *mut _ as isize + 5);
+    pub unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        self.state = 0;
+
+        #[cfg(feature = "registry")]
+        registry::leave(self.id);
+
+        Ok(())
+    }
+
+    pub unsafe fn unhook(&mut self) {
+        self.try_unhook().unwrap()
     }
 
     #[inline(always)]
     #[allow(clippy::manual_swap)]
     pub unsafe fn toggle_inline(&mut self) {
-        let target: isize = util::transmute(self.detour_target);
-        let target = (target + self as *mut _ as isize) as *mut _;
+        let target = A::decode(self.base(), self.target) as *mut _;
 
         let scratch = self.scratch;
         self.scratch = *target;
         *target = scratch;
+
+        self.state ^= ENABLED;
+    }
+
+    unsafe fn exchange(&mut self, killed: bool) {
+        if !killed {
+            return self.toggle_inline();
+        }
+
+        self.scratch = if self.is_enabled() {
+            self.jump()
+        } else {
+            *(A::decode(self.base(), self.target) as *const [u8; 5])
+        };
+
+        self.state ^= ENABLED;
+    }
+
+    #[inline(always)]
+    unsafe fn exchange_inline(&mut self) {
+        let _ = self.guarded(|hook, killed| {
+            hook.exchange(killed);
+            Ok(())
+        });
+    }
+
+    pub unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        self.lock_inline();
+
+        let result = self.guarded(|hook, killed| {
+            if killed && !hook.is_enabled() {
+                return Err(Error::Killed);
+            }
+
+            hook.exchange(killed);
+
+            Ok(())
+        });
+
+        self.unlock_inline();
+
+        result
+    }
+
+    #[doc(hidden)]
+    pub unsafe fn toggle_orig(&mut self) {
+        self.lock_inline();
+        self.exchange_inline();
+        self.unlock_inline();
     }
 
     pub unsafe fn toggle(&mut self) {
-        self.toggle_inline()
+        self.try_toggle().unwrap()
+    }
+
+    #[inline(always)]
+    pub unsafe fn orig_inline(&mut self) -> Orig<'_, T, A> {
+        self.lock_inline();
+        self.exchange_inline();
+
+        Orig { hook: self }
+    }
+
+    pub unsafe fn try_enable(&mut self) -> Result<(), Error> {
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn enable(&mut self) {
+        self.try_enable().unwrap()
+    }
+
+    pub unsafe fn try_disable(&mut self) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Err(Error::Disabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn disable(&mut self) {
+        self.try_disable().unwrap()
     }
 
     #[inline(always)]
     pub unsafe fn target_inline(&self) -> T {
-        let target: isize = util::transmute(self.detour_target);
-        util::transmute(target + self as *const _ as isize)
+        T::from_addr(A::decode(self.base(), self.target) as usize)
     }
 
     pub unsafe fn target(&self) -> T {
         self.target_inline()
     }
+
+    pub unsafe fn info(&self) -> Option<HookInfo> {
+        if !self.is_installed() {
+            return None;
+        }
+
+        let target = A::decode(self.base(), self.target) as *const [u8; 5];
+
+        let original = if self.is_enabled() {
+            self.scratch
+        } else {
+            *target
+        };
+
+        Some(HookInfo {
+            target: target as usize,
+            detour: self.detour.to_addr(),
+            trampoline: 0,
+            trampoline_len: 0,
+            original,
+            patched: self.jump(),
+            stolen: 0,
+        })
+    }
+
+    unsafe fn jump(&self) -> [u8; 5] {
+        let target = A::decode(self.base(), self.target);
+        let offset = (self.detour.to_addr() as isize - target - 5) as i32;
+
+        let mut jump = [0xE9, 0, 0, 0, 0];
+        jump[1..].copy_from_slice(&offset.to_ne_bytes());
+        jump
+    }
+
+    #[cfg(feature = "registry")]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        registry::guard(|guard| {
+            let result = f(self, guard.killed);
+
+            if let Some(info) = self.info() {
+                guard.update(self.id, info, self.is_enabled());
+            }
+
+            result
+        })
+    }
+
+    #[cfg(not(feature = "registry"))]
+    #[inline(always)]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        f(self, false)
+    }
+}
+
+impl<T: FnPtr, A: Addressing> Patch for Hook<T, A> {
+    type Target = T;
+
+    fn is_installed(&self) -> bool {
+        Hook::is_installed(self)
+    }
+
+    fn is_enabled(&self) -> bool {
+        Hook::is_enabled(self)
+    }
+
+    unsafe fn info(&self) -> Option<HookInfo> {
+        Hook::info(self)
+    }
+
+    unsafe fn try_hook(&mut self, target: T) -> Result<(), Error> {
+        Hook::try_hook(self, target)
+    }
+
+    unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        Hook::try_unhook(self)
+    }
+
+    unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        Hook::try_toggle(self)
+    }
 }
 
 #[macro_export]
 macro_rules! local_swap_hook {
+    {
+        @dollar($dollar:tt)
+
+        #[filter($filter:path)]
+        $($tt:tt)*
+    } => {
+        compile_error!("#[filter] is only supported on trampoline hooks");
+    };
+
+    {
+        @dollar($dollar:tt)
+
+        #[lock]
+        #[guard]
+        $($tt:tt)*
+    } => {
+        $crate::local_swap_hook! {
+            @dollar($dollar)
+
+            #[guard]
+            #[lock]
+            $($tt)*
+        }
+    };
+
+    {
+        @dollar($dollar:tt)
+
+        #[lock]
+        $(#[$($attr:tt)*])* $vis:vis
+        $(unsafe $($unsafe:lifetime)?)? $(extern $($abi:literal)?)?
+        fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)? $body:block
+    } => {
+        $crate::local_swap_hook! {
+            @dollar($dollar)
+
+            $(#[$($attr)*])* $vis
+            $(unsafe $($unsafe)?)? $(extern $($abi)?)?
+            fn $name($($arg: $ty),*) $(-> $ret)? {
+                #[allow(unused_macros)]
+                macro_rules! orig {
+                    ($dollar($dollar arg:tt)*) => {
+                        {
+                            #[allow(unused_unsafe)]
+                            let __ez_orig = unsafe { super::__ez_HOOK.orig_inline() };
+
+                            #[allow(unused_unsafe)]
+                            let target = unsafe { __ez_orig.target() };
+
+                            target($dollar($dollar arg)*)
+                        }
+                    };
+                }
+
+                $body
+            }
+        }
+    };
+
+    {
+        @dollar($dollar:tt)
+
+        #[guard]
+        $(#[$($attr:tt)*])* $vis:vis
+        $(unsafe $($unsafe:lifetime)?)? $(extern $($abi:literal)?)?
+        fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)? $body:block
+    } => {
+        $crate::local_swap_hook! {
+            @dollar($dollar)
+
+            $(#[$($attr)*])* $vis
+            $(unsafe $($unsafe)?)? $(extern $($abi)?)?
+            fn $name($($arg: $ty),*) $(-> $ret)? {
+                $crate::__private::thread_local! {
+                    static __EZ_DEPTH: $crate::__private::Cell<usize> =
+                        $crate::__private::Cell::new(0);
+                }
+
+                let __ez_guard = $crate::guard::Guard::enter(&__EZ_DEPTH);
+
+                #[allow(unused_macros)]
+                macro_rules! depth {
+                    () => {
+                        __ez_guard.depth()
+                    };
+                }
+
+                if __ez_guard.depth() > 1 {
+                    return orig!($($arg),*);
+                }
+
+                $body
+            }
+        }
+    };
+
     {
         @dollar($dollar:tt)
 
@@ -92,7 +498,7 @@ macro_rules! local_swap_hook {
                         {
                             #[allow(unused_unsafe)]
                             let target = unsafe {
-                                super::toggle();
+                                super::__ez_HOOK.toggle_orig();
 
                                 super::target()
                             };
@@ -101,7 +507,7 @@ macro_rules! local_swap_hook {
 
                             #[allow(unused_unsafe)]
                             unsafe {
-                                super::toggle();
+                                super::__ez_HOOK.toggle_orig();
                             }
 
                             result
@@ -128,10 +534,15 @@ macro_rules! local_swap_hook {
                 $crate::local::swap::Hook::new(__ez_hook::$name)
             };
 
+            #[allow(dead_code)]
             pub unsafe fn hook(target: __ez_Func) {
                 __ez_HOOK.hook(target)
             }
 
+            $crate::__ez_hook_symbol! {}
+
+            $crate::__ez_preload! {}
+
             #[allow(dead_code)]
             pub unsafe fn unhook() {
                 __ez_HOOK.unhook()
@@ -142,10 +553,35 @@ macro_rules! local_swap_hook {
                 __ez_HOOK.toggle()
             }
 
+            #[allow(dead_code)]
+            pub unsafe fn enable() {
+                __ez_HOOK.enable()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn disable() {
+                __ez_HOOK.disable()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn is_installed() -> bool {
+                __ez_HOOK.is_installed()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn is_enabled() -> bool {
+                __ez_HOOK.is_enabled()
+            }
+
             #[allow(dead_code)]
             pub unsafe fn target() -> __ez_Func {
                 __ez_HOOK.target()
             }
+
+            #[allow(dead_code)]
+            pub unsafe fn info() -> Option<$crate::HookInfo> {
+                __ez_HOOK.info()
+            }
         }
     };
 
@@ -167,6 +603,10 @@ mod tests {
         x
     }
 
+    fn negate(x: i32) -> i32 {
+        -x
+    }
+
     local_swap_hook! {
         fn add_one_before(x: i32) -> i32 {
             orig!(x + 1)
@@ -291,4 +731,268 @@ mod tests {
         assert_eq!(square(4), 16);
         assert_eq!(square(5), 25);
     }
+
+    #[test]
+    fn state() {
+        setup();
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(identity) };
+
+        assert!(!hook.is_installed());
+        assert_eq!(unsafe { hook.try_toggle() }, Err(Error::NotInstalled));
+        assert_eq!(unsafe { hook.info() }, None);
+
+        unsafe { hook.hook(square) };
+
+        assert!(hook.is_installed());
+        assert!(!hook.is_enabled());
+        assert_eq!(
+            unsafe { hook.try_hook(square) },
+            Err(Error::AlreadyInstalled)
+        );
+        assert_eq!(unsafe { hook.try_disable() }, Err(Error::Disabled));
+
+        let info = unsafe { hook.info() }.unwrap();
+        assert_eq!(info.target, square as usize);
+        assert_eq!(info.detour, identity as usize);
+        assert_eq!(info.patched[0], 0xE9);
+        assert_eq!(info.stolen, 0);
+
+        unsafe { hook.enable() };
+
+        assert!(hook.is_enabled());
+        assert_eq!(unsafe { hook.try_enable() }, Err(Error::Enabled));
+        assert_eq!(unsafe { hook.try_unhook() }, Err(Error::Enabled));
+        assert_eq!(unsafe { hook.info() }, Some(info));
+        assert_eq!(square(4), 4);
+
+        unsafe { hook.disable() };
+        unsafe { hook.unhook() };
+
+        assert!(!hook.is_installed());
+        assert_eq!(square(4), 16);
+    }
+
+    #[test]
+    fn set_detour() {
+        setup();
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(identity) };
+
+        unsafe { hook.hook(square) };
+        unsafe { hook.enable() };
+
+        assert_eq!(square(4), 4);
+
+        unsafe { hook.set_detour(negate) };
+
+        assert!(unsafe { hook.target() } == square);
+        assert_eq!(square(4), -4);
+
+        unsafe { hook.disable() };
+        unsafe { hook.set_detour(identity) };
+
+        assert_eq!(square(4), 16);
+
+        unsafe { hook.enable() };
+
+        assert_eq!(square(4), 4);
+        assert_eq!(unsafe { hook.info() }.unwrap().detour, identity as usize);
+
+        unsafe { hook.disable() };
+        unsafe { hook.unhook() };
+
+        assert_eq!(square(4), 16);
+    }
+
+    #[test]
+    fn moved() {
+        setup();
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(identity) };
+        unsafe { hook.hook(square) };
+
+        let mut moved = [unsafe { Hook::<fn(i32) -> i32>::new(identity) }, hook];
+
+        assert!(unsafe { moved[1].target() } == square);
+
+        unsafe { moved[1].enable() };
+
+        assert_eq!(square(4), 4);
+
+        unsafe { moved[1].disable() };
+        unsafe { moved[1].unhook() };
+
+        assert_eq!(square(4), 16);
+    }
+
+    #[test]
+    fn orig_concurrent() {
+        extern crate std;
+
+        use std::{thread, vec::Vec};
+
+        local_swap_hook! {
+            #[lock]
+            fn add_one_concurrent(x: i32) -> i32 {
+                orig!(x + 1)
+            }
+        }
+
+        #[inline(never)]
+        fn cube(x: i32) -> i32 {
+            util::black_box(x * x * x)
+        }
+
+        util::unprotect(cube as _, 5);
+
+        unsafe { add_one_concurrent::hook(cube) };
+        unsafe { add_one_concurrent::enable() };
+
+        let detour =
+            unsafe { <fn(i32) -> i32>::from_addr(add_one_concurrent::info().unwrap().detour) };
+
+        let threads = (0..4)
+            .map(|_| {
+                thread::spawn(move || {
+                    for _ in 0..10000 {
+                        assert_eq!(detour(2), 27);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(unsafe { add_one_concurrent::is_enabled() });
+        assert_eq!(cube(2), 27);
+
+        unsafe { add_one_concurrent::disable() };
+        unsafe { add_one_concurrent::unhook() };
+
+        assert_eq!(cube(2), 8);
+    }
+
+    #[test]
+    fn orig_unwind() {
+        extern crate std;
+
+        use std::panic;
+
+        local_swap_hook! {
+            #[lock]
+            fn checked(x: i32) -> i32 {
+                orig!(x)
+            }
+        }
+
+        #[inline(never)]
+        fn positive(x: i32) -> i32 {
+            assert!(util::black_box(x) > 0);
+            x
+        }
+
+        util::unprotect(positive as _, 5);
+
+        unsafe { checked::hook(positive) };
+        unsafe { checked::enable() };
+
+        assert!(panic::catch_unwind(|| positive(-1)).is_err());
+        assert!(unsafe { checked::is_enabled() });
+        assert_eq!(positive(2), 2);
+
+        unsafe { checked::disable() };
+        unsafe { checked::unhook() };
+    }
+
+    #[cfg(feature = "std")]
+    #[inline(never)]
+    fn quad(x: i32) -> i32 {
+        util::black_box(x * x)
+    }
+
+    #[cfg(feature = "std")]
+    local_swap_hook! {
+        #[guard]
+        fn double(x: i32) -> i32 {
+            assert_eq!(depth!(), 1);
+            quad(x) + orig!(x)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn guard() {
+        util::unprotect(quad as _, 5);
+
+        unsafe { double::hook(quad) };
+        unsafe { double::enable() };
+
+        assert_eq!(quad(4), 32);
+        assert_eq!(quad(4), 32);
+
+        unsafe { double::disable() };
+        unsafe { double::unhook() };
+
+        assert_eq!(quad(4), 16);
+    }
+
+    #[cfg(feature = "std")]
+    #[inline(never)]
+    fn quint(x: i32) -> i32 {
+        util::black_box(x * 5)
+    }
+
+    #[cfg(feature = "std")]
+    #[inline(never)]
+    fn sext(x: i32) -> i32 {
+        util::black_box(x * 6)
+    }
+
+    #[cfg(feature = "std")]
+    local_swap_hook! {
+        #[lock]
+        #[guard]
+        fn locked_guarded(x: i32) -> i32 {
+            assert_eq!(depth!(), 1);
+            quint(x) + orig!(x)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    local_swap_hook! {
+        #[guard]
+        #[lock]
+        fn guarded_locked(x: i32) -> i32 {
+            assert_eq!(depth!(), 1);
+            sext(x) + orig!(x)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn combined() {
+        util::unprotect(quint as _, 5);
+        util::unprotect(sext as _, 5);
+
+        unsafe { locked_guarded::hook(quint) };
+        unsafe { locked_guarded::enable() };
+        unsafe { guarded_locked::hook(sext) };
+        unsafe { guarded_locked::enable() };
+
+        assert_eq!(quint(2), 20);
+        assert_eq!(sext(2), 24);
+        assert!(unsafe { locked_guarded::is_enabled() });
+        assert!(unsafe { guarded_locked::is_enabled() });
+
+        unsafe { locked_guarded::disable() };
+        unsafe { locked_guarded::unhook() };
+        unsafe { guarded_locked::disable() };
+        unsafe { guarded_locked::unhook() };
+
+        assert_eq!(quint(2), 10);
+        assert_eq!(sext(2), 12);
+    }
 }
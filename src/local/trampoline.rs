@@ -1,106 +1,736 @@
-use crate::util;
+use crate::{
+    decode,
+    info::{ENABLED, INSTALLED},
+    thunk, util, Absolute, Addressing, Error, FnPtr, HookInfo, Patch,
+};
 
-use core::convert::{TryFrom, TryInto};
+#[cfg(all(feature = "closure", target_os = "linux"))]
+use crate::closure::{Closure, Owned};
 
-#[cfg(target_arch = "x86")]
-use lde::X86;
+#[cfg(all(feature = "reclaim", target_os = "linux"))]
+use crate::{flight, pool};
 
-#[cfg(target_arch = "x86_64")]
-use lde::X64 as X86;
+#[cfg(all(feature = "reclaim", target_os = "linux"))]
+use core::sync::atomic::AtomicUsize;
 
-pub struct Hook<T: 'static> {
-    detour_target: T,
+#[cfg(all(any(feature = "vdso", feature = "deferred"), target_os = "linux"))]
+use crate::{near, protect};
+
+#[cfg(feature = "registry")]
+use crate::registry;
+
+use core::{
+    convert::TryFrom,
+    marker::PhantomData,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+const ARMED: u8 = 4;
+
+#[cfg(all(any(feature = "vdso", feature = "deferred"), target_os = "linux"))]
+const STUB: usize = 32;
+
+#[cfg(all(any(feature = "vdso", feature = "deferred"), target_os = "linux"))]
+pub(crate) const RELAY: usize = 64;
+
+pub struct Hook<T: 'static, A: Addressing = Absolute> {
+    detour: T,
+    target: isize,
     trampoline: isize,
+    stub: isize,
+    flag: isize,
+    state: u8,
+    #[cfg(feature = "registry")]
+    id: usize,
+    stolen_len: u8,
+    stolen_count: u8,
+    follow: bool,
+    original: [u8; 5],
+    swap: [u8; 5],
+    restore: unsafe fn(&HookInfo, bool) -> Result<(), Error>,
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    counter: isize,
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    pooled: bool,
+    #[cfg(all(feature = "closure", target_os = "linux"))]
+    closure: Option<Owned>,
+    addressing: PhantomData<A>,
 }
 
-impl<T> Hook<T> {
+impl<T, A: Addressing> Hook<T, A> {
     pub const unsafe fn new(detour: T) -> Self {
         Self {
-            detour_target: detour,
+            detour,
+            target: 0,
             trampoline: 0,
+            stub: 0,
+            flag: 0,
+            state: 0,
+            #[cfg(feature = "registry")]
+            id: 0,
+            stolen_len: 0,
+            stolen_count: 0,
+            follow: false,
+            original: [0; 5],
+            swap: [0; 5],
+            restore: util::restore,
+            #[cfg(all(feature = "reclaim", target_os = "linux"))]
+            counter: 0,
+            #[cfg(all(feature = "reclaim", target_os = "linux"))]
+            pooled: false,
+            #[cfg(all(feature = "closure", target_os = "linux"))]
+            closure: None,
+            addressing: PhantomData,
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.state & INSTALLED != 0
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state & ENABLED != 0
+    }
+
+    #[inline(always)]
+    fn base(&self) -> isize {
+        self as *const _ as isize
+    }
+
+    #[inline(always)]
+    fn flag(&self) -> &AtomicU8 {
+        unsafe { &*(A::decode(self.base(), self.flag) as *const AtomicU8) }
+    }
+
+    fn is_armed(&self) -> bool {
+        self.state & ARMED != 0
+    }
+
+    fn dispatch(&self) -> isize {
+        let stub = A::decode(self.base(), self.stub);
+
+        #[cfg(all(feature = "reclaim", target_os = "linux"))]
+        if self.counter != 0 {
+            return stub + flight::PREFIX as isize;
         }
+
+        stub
     }
 }
 
-impl<T: Copy> Hook<T> {
+impl<T: FnPtr, A: Addressing> Hook<T, A> {
+    pub unsafe fn try_set_detour(&mut self, detour: T) -> Result<(), Error> {
+        self.guarded(|hook, killed| {
+            if hook.is_installed() && hook.stub != 0 {
+                let detour = detour.to_addr() as isize;
+                let dispatch = hook.dispatch();
+
+                let offset =
+                    i32::try_from(detour - dispatch - 13).map_err(|_| Error::OutOfRange)?;
+
+                util::patch((dispatch + 9) as *mut u8, &offset.to_ne_bytes())?;
+            } else if hook.is_installed() {
+                let detour = detour.to_addr() as isize;
+                let target = A::decode(hook.base(), hook.target);
+
+                let offset = i32::try_from(detour - target - 5).map_err(|_| Error::OutOfRange)?;
+
+                if !hook.is_enabled() {
+                    hook.swap[1..5].copy_from_slice(&offset.to_ne_bytes());
+                } else if !killed {
+                    util::patch((target + 1) as *mut u8, &offset.to_ne_bytes())?;
+                }
+            }
+
+            hook.detour = detour;
+
+            Ok(())
+        })
+    }
+
     pub unsafe fn set_detour(&mut self, detour: T) {
-        self.detour_target = detour;
+        self.try_set_detour(detour).unwrap()
+    }
+
+    pub fn set_follow_thunks(&mut self, follow: bool) {
+        self.follow = follow;
     }
 
     pub unsafe fn set_trampoline(&mut self, trampoline: &'static mut [u8; 24]) {
-        trampoline[0] = 0xE9;
-        self.trampoline = trampoline.as_ptr() as isize - self as *mut _ as isize;
+        self.trampoline = A::encode(self.base(), trampoline.as_ptr() as isize);
     }
 
-    pub unsafe fn hook(&mut self, target: T) {
-        let detour: isize = util::transmute(self.detour_target);
-        let target: isize = util::transmute(target);
+    pub unsafe fn set_stub(&mut self, stub: &'static mut [u8; 24], flag: &'static AtomicU8) {
+        self.stub = A::encode(self.base(), stub.as_ptr() as isize);
+        self.flag = A::encode(self.base(), flag as *const _ as isize);
+    }
+
+    #[cfg(all(feature = "vdso", target_os = "linux"))]
+    pub(crate) fn set_restore(&mut self, restore: unsafe fn(&HookInfo, bool) -> Result<(), Error>) {
+        self.restore = restore;
+    }
+
+    pub unsafe fn try_hook(&mut self, target: T) -> Result<(), Error> {
+        #[cfg(feature = "registry")]
+        registry::check()?;
+
+        if self.is_installed() {
+            return Err(Error::AlreadyInstalled);
+        }
+
+        if self.trampoline == 0 {
+            return Err(Error::NoTrampoline);
+        }
+
+        let detour = self.detour.to_addr() as isize;
+        let target = if self.follow {
+            thunk::resolve(target.to_addr()) as isize
+        } else {
+            target.to_addr() as isize
+        };
 
-        let trampoline = &mut *((self.trampoline + self as *mut _ as isize) as *mut [u8; 24]);
+        let trampoline = &mut *(A::decode(self.base(), self.trampoline) as *mut [u8; 24]);
 
-        let offset = i32::try_from(detour - target - 5).unwrap();
-        trampoline[1..5].copy_from_slice(&offset.to_ne_bytes());
+        let back = i32::try_from(target - trampoline.as_ptr() as isize - 5)
+            .map_err(|_| Error::OutOfRange)?;
 
-        let offset = i32::try_from(target - trampoline.as_ptr() as isize - 5).unwrap();
+        if self.stub != 0 {
+            i32::try_from(A::decode(self.base(), self.stub) - target - 5)
+                .map_err(|_| Error::OutOfRange)?;
 
-        self.detour_target = util::transmute(target - self as *mut _ as isize);
-        let target = &*(target as *const [u8; 19]);
+            #[cfg(all(feature = "reclaim", target_os = "linux"))]
+            if self.counter != 0 {
+                let stub = A::decode(self.base(), self.stub) as usize;
+                let counter = A::decode(self.base(), self.counter) as usize;
 
-        let mut count = 0;
-        while count < 5 {
-            count += X86.ld(&target[count..]) as usize;
+                flight::prefix(stub, counter).ok_or(Error::OutOfRange)?;
+            }
+
+            let stub = &mut *(self.dispatch() as *mut [u8; 24]);
+            let base = stub.as_ptr() as isize;
+
+            let flag = A::decode(self.base(), self.flag);
+            let disabled = i32::try_from(trampoline.as_ptr() as isize - base - 18)
+                .map_err(|_| Error::OutOfRange)?;
+            let enabled = i32::try_from(detour - base - 13).map_err(|_| Error::OutOfRange)?;
+
+            #[cfg(target_arch = "x86")]
+            let flag = flag as i32;
+
+            #[cfg(target_arch = "x86_64")]
+            let flag = i32::try_from(flag - base - 7).map_err(|_| Error::OutOfRange)?;
+
+            self.flag().store(0, Ordering::SeqCst);
+
+            stub[0..2].copy_from_slice(&[0x80, 0x3D]);
+            stub[2..6].copy_from_slice(&flag.to_ne_bytes());
+            stub[6..9].copy_from_slice(&[0x00, 0x0F, 0x85]);
+            stub[9..13].copy_from_slice(&enabled.to_ne_bytes());
+            stub[13] = 0xE9;
+            stub[14..18].copy_from_slice(&disabled.to_ne_bytes());
+        } else {
+            i32::try_from(detour - target - 5).map_err(|_| Error::OutOfRange)?;
+
+            self.swap = self.jump(target, detour);
         }
 
-        trampoline[5..count].copy_from_slice(&target[5..count]);
+        let (len, count) = decode::copy(target as usize, trampoline.as_ptr() as usize)?;
 
-        let jump = &mut trampoline[count..];
-        jump[0] = 0xE9;
-        jump[1..5].copy_from_slice(&offset.to_ne_bytes());
+        self.original = *(target as *const [u8; 5]);
+
+        let back_jump = &mut trampoline[len..];
+        back_jump[0] = 0xE9;
+        back_jump[1..5].copy_from_slice(&back.to_ne_bytes());
+
+        self.target = A::encode(self.base(), target);
+        self.stolen_len = len as u8;
+        self.stolen_count = count as u8;
+        self.state = INSTALLED;
+
+        #[cfg(feature = "registry")]
+        match registry::join(self.info().unwrap(), false, self.restore) {
+            Ok(id) => self.id = id,
+            Err(error) => {
+                self.try_unhook()?;
+                return Err(error);
+            }
+        }
+
+        Ok(())
     }
 
-    pub unsafe fn unhook(&mut self) {
-        let trampoline = &*((self.trampoline + self as *mut _ as isize) as *const [u8; 5]);
+    pub unsafe fn hook(&mut self, target: T) {
+        self.try_hook(target).unwrap()
+    }
+
+    pub unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        if self.is_armed() {
+            (self.restore)(&self.info().unwrap(), false)?;
+        }
 
-        let offset = i32::from_ne_bytes(trampoline[1..].try_into().unwrap()) as isize;
+        self.state = 0;
 
-        let target: isize = util::transmute(self.detour_target);
-        self.detour_target = util::transmute(offset + target + self as *mut _ as isize + 5);
+        #[cfg(feature = "registry")]
+        registry::leave(self.id);
+
+        #[cfg(all(feature = "closure", target_os = "linux"))]
+        if let Some(closure) = self.closure.take() {
+            closure.free();
+            self.trampoline = 0;
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn unhook(&mut self) {
+        self.try_unhook().unwrap()
     }
 
     #[inline(always)]
     #[allow(clippy::manual_swap)]
     pub unsafe fn toggle_inline(&mut self) {
-        let trampoline = (self.trampoline + self as *mut _ as isize) as *mut [u8; 5];
+        if self.stub != 0 {
+            self.state ^= ENABLED;
+            self.flag().store(self.state & ENABLED, Ordering::SeqCst);
+            return;
+        }
 
-        let target: isize = util::transmute(self.detour_target);
-        let target = (target + self as *mut _ as isize) as *mut _;
+        let target = A::decode(self.base(), self.target) as *mut [u8; 5];
 
-        let scratch = *trampoline;
-        *trampoline = *target;
+        let scratch = self.swap;
+        self.swap = *target;
         *target = scratch;
+
+        self.state ^= ENABLED;
+    }
+
+    pub unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        self.guarded(|hook, killed| {
+            if killed && !hook.is_enabled() {
+                return Err(Error::Killed);
+            }
+
+            if hook.stub != 0 && !hook.is_armed() {
+                (hook.restore)(&hook.info().unwrap(), true)?;
+                hook.state |= ARMED;
+            }
+
+            if killed && hook.stub == 0 {
+                let target = A::decode(hook.base(), hook.target);
+
+                hook.swap = hook.jump(target, hook.detour.to_addr() as isize);
+                hook.state ^= ENABLED;
+            } else {
+                hook.toggle_inline();
+            }
+
+            Ok(())
+        })
     }
 
     pub unsafe fn toggle(&mut self) {
-        self.toggle_inline()
+        self.try_toggle().unwrap()
+    }
+
+    pub unsafe fn try_enable(&mut self) -> Result<(), Error> {
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn enable(&mut self) {
+        self.try_enable().unwrap()
+    }
+
+    pub unsafe fn try_disable(&mut self) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Err(Error::Disabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn disable(&mut self) {
+        self.try_disable().unwrap()
     }
 
     pub unsafe fn target(&self) -> T {
-        let target: isize = util::transmute(self.detour_target);
-        util::transmute(target + self as *const _ as isize)
+        T::from_addr(A::decode(self.base(), self.target) as usize)
     }
 
     #[inline(always)]
     pub unsafe fn trampoline_inline(&self) -> T {
-        util::transmute(self.trampoline + self as *const _ as isize)
+        T::from_addr(A::decode(self.base(), self.trampoline) as usize)
     }
 
     pub unsafe fn trampoline(&self) -> T {
         self.trampoline_inline()
     }
+
+    pub unsafe fn info(&self) -> Option<HookInfo> {
+        if !self.is_installed() {
+            return None;
+        }
+
+        let target = A::decode(self.base(), self.target) as *const [u8; 5];
+        let trampoline = A::decode(self.base(), self.trampoline) as *const [u8; 5];
+
+        let (original, patched) = if self.stub != 0 {
+            let stub = A::decode(self.base(), self.stub);
+            (self.original, self.jump(target as isize, stub))
+        } else if self.is_enabled() {
+            let detour = self.detour.to_addr() as isize;
+            (self.swap, self.jump(target as isize, detour))
+        } else {
+            (*target, self.swap)
+        };
+
+        Some(HookInfo {
+            target: target as usize,
+            detour: self.detour.to_addr(),
+            trampoline: trampoline as usize,
+            trampoline_len: self.stolen_len as usize + 5,
+            original,
+            patched,
+            stolen: self.stolen_count as usize,
+        })
+    }
+
+    fn jump(&self, target: isize, destination: isize) -> [u8; 5] {
+        let entry = (destination - target - 5) as i32;
+
+        let mut jump = [0xE9; 5];
+        jump[1..5].copy_from_slice(&entry.to_ne_bytes());
+        jump
+    }
+
+    #[cfg(feature = "registry")]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        registry::guard(|guard| {
+            let result = f(self, guard.killed);
+
+            if let Some(info) = self.info() {
+                guard.update(self.id, info, self.is_armed() || self.is_enabled());
+            }
+
+            result
+        })
+    }
+
+    #[cfg(not(feature = "registry"))]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        f(self, false)
+    }
+}
+
+#[cfg(all(feature = "reclaim", target_os = "linux"))]
+impl<T: FnPtr, A: Addressing> Hook<T, A> {
+    fn counter(&self) -> Option<&AtomicUsize> {
+        if self.counter == 0 {
+            return None;
+        }
+
+        Some(unsafe { &*(A::decode(self.base(), self.counter) as *const AtomicUsize) })
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.counter().map_or(0, |counter| {
+            counter.load(Ordering::SeqCst) & !flight::LEAKED
+        })
+    }
+
+    pub fn is_quiescent(&self) -> bool {
+        self.in_flight() == 0
+    }
+
+    pub unsafe fn try_hook_allocated_stub(&mut self, target: T) -> Result<(), Error> {
+        if self.is_installed() {
+            return Err(Error::AlreadyInstalled);
+        }
+
+        if self.pooled {
+            self.try_free()?;
+        }
+
+        let near = if self.follow {
+            thunk::resolve(target.to_addr())
+        } else {
+            target.to_addr()
+        };
+
+        let slot = pool::allocate(near).ok_or(Error::AllocationFailed)?;
+        let counter = &*(pool::counter(slot) as *const AtomicUsize);
+        counter.store(0, Ordering::SeqCst);
+
+        self.set_trampoline(&mut *(slot as *mut [u8; 24]));
+        self.set_stub(
+            &mut *((slot + 32) as *mut [u8; 24]),
+            &*(pool::flag(slot) as *const AtomicU8),
+        );
+        self.counter = A::encode(self.base(), counter as *const _ as isize);
+
+        if let Err(error) = self.try_hook(target) {
+            self.trampoline = 0;
+            self.stub = 0;
+            self.counter = 0;
+            pool::free(slot);
+            return Err(error);
+        }
+
+        self.pooled = true;
+
+        Ok(())
+    }
+
+    pub unsafe fn hook_allocated_stub(&mut self, target: T) {
+        self.try_hook_allocated_stub(target).unwrap()
+    }
+
+    pub unsafe fn try_free(&mut self) -> Result<(), Error> {
+        if self.is_installed() {
+            return Err(Error::AlreadyInstalled);
+        }
+
+        if !self.pooled {
+            return Err(Error::NoTrampoline);
+        }
+
+        let leaked = self
+            .counter()
+            .is_some_and(|counter| counter.load(Ordering::SeqCst) & flight::LEAKED != 0);
+
+        if !leaked && !self.is_quiescent() {
+            return Err(Error::ThreadInPatch);
+        }
+
+        if !leaked {
+            pool::free(A::decode(self.base(), self.trampoline) as usize);
+        }
+
+        self.trampoline = 0;
+        self.stub = 0;
+        self.counter = 0;
+        self.pooled = false;
+
+        Ok(())
+    }
+
+    pub unsafe fn try_unhook_and_free(&mut self) -> Result<(), Error> {
+        if self.is_enabled() {
+            self.try_disable()?;
+        }
+
+        if self.is_installed() {
+            self.try_unhook()?;
+        }
+
+        loop {
+            match self.try_free() {
+                Err(Error::ThreadInPatch) => core::hint::spin_loop(),
+                result => return result,
+            }
+        }
+    }
+
+    pub unsafe fn unhook_and_free(&mut self) {
+        self.try_unhook_and_free().unwrap()
+    }
+}
+
+#[cfg(all(any(feature = "vdso", feature = "deferred"), target_os = "linux"))]
+impl<T: FnPtr> Hook<T> {
+    pub(crate) unsafe fn try_hook_relayed(&mut self, target: T) -> Result<usize, Error> {
+        let size = protect::page_size();
+        let detour = self.detour;
+
+        let memory = near::allocate(target.to_addr(), 2 * size).ok_or(Error::AllocationFailed)?;
+
+        if !protect::protect(memory + size, libc::PROT_READ | libc::PROT_WRITE) {
+            near::free(memory, 2 * size);
+            return Err(Error::ProtectFailed);
+        }
+
+        near::jump(memory + RELAY, detour.to_addr());
+
+        self.detour = T::from_addr(memory + RELAY);
+        self.set_trampoline(&mut *(memory as *mut [u8; 24]));
+        self.set_stub(
+            &mut *((memory + STUB) as *mut [u8; 24]),
+            &*((memory + size) as *const AtomicU8),
+        );
+
+        if let Err(error) = self.try_hook(target) {
+            self.detour = detour;
+            self.trampoline = 0;
+            self.stub = 0;
+            near::free(memory, 2 * size);
+            return Err(error);
+        }
+
+        Ok(memory)
+    }
+
+    pub(crate) unsafe fn free_relayed(&mut self, memory: usize) {
+        self.trampoline = 0;
+        self.stub = 0;
+        near::free(memory, 2 * protect::page_size());
+    }
+}
+
+#[cfg(all(feature = "closure", target_os = "linux"))]
+impl<T: FnPtr> Hook<T> {
+    pub unsafe fn with_closure<F>(target: T, closure: F) -> Result<Self, Error>
+    where
+        T: Closure<F>,
+    {
+        let closure = Owned::new::<T, F>(target.to_addr(), closure)?;
+
+        let mut hook = Self::new(T::from_addr(closure.thunk()));
+        hook.set_trampoline(closure.trampoline());
+
+        if let Err(error) = hook.try_hook(target) {
+            closure.free();
+            return Err(error);
+        }
+
+        hook.closure = Some(closure);
+
+        Ok(hook)
+    }
+}
+
+impl<T: FnPtr, A: Addressing> Patch for Hook<T, A> {
+    type Target = T;
+
+    fn is_installed(&self) -> bool {
+        Hook::is_installed(self)
+    }
+
+    fn is_enabled(&self) -> bool {
+        Hook::is_enabled(self)
+    }
+
+    unsafe fn info(&self) -> Option<HookInfo> {
+        Hook::info(self)
+    }
+
+    unsafe fn try_hook(&mut self, target: T) -> Result<(), Error> {
+        Hook::try_hook(self, target)
+    }
+
+    unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        Hook::try_unhook(self)
+    }
+
+    unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        Hook::try_toggle(self)
+    }
+}
+
+#[cfg(all(feature = "reclaim", target_os = "linux"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ez_reclaim {
+    () => {
+        #[allow(dead_code)]
+        pub unsafe fn hook_allocated_stub(target: __ez_Func) {
+            __ez_HOOK.hook_allocated_stub(target)
+        }
+
+        #[allow(dead_code)]
+        pub unsafe fn unhook_and_free() {
+            __ez_HOOK.unhook_and_free()
+        }
+
+        #[allow(dead_code)]
+        pub unsafe fn in_flight() -> usize {
+            __ez_HOOK.in_flight()
+        }
+    };
 }
 
 #[macro_export]
 macro_rules! local_trampoline_hook {
+    {
+        @dollar($dollar:tt)
+
+        #[filter($filter:path)]
+        $(#[$($attr:tt)*])* $vis:vis
+        $(unsafe $($unsafe:lifetime)?)? $(extern $($abi:literal)?)?
+        fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)? $body:block
+    } => {
+        $crate::local_trampoline_hook! {
+            @dollar($dollar)
+
+            $(#[$($attr)*])* $vis
+            $(unsafe $($unsafe)?)? $(extern $($abi)?)?
+            fn $name($($arg: $ty),*) $(-> $ret)? {
+                if !$filter.contains_current() {
+                    return orig!($($arg),*);
+                }
+
+                $body
+            }
+        }
+    };
+
+    {
+        @dollar($dollar:tt)
+
+        #[guard]
+        $(#[$($attr:tt)*])* $vis:vis
+        $(unsafe $($unsafe:lifetime)?)? $(extern $($abi:literal)?)?
+        fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)? $body:block
+    } => {
+        $crate::local_trampoline_hook! {
+            @dollar($dollar)
+
+            $(#[$($attr)*])* $vis
+            $(unsafe $($unsafe)?)? $(extern $($abi)?)?
+            fn $name($($arg: $ty),*) $(-> $ret)? {
+                $crate::__private::thread_local! {
+                    static __EZ_DEPTH: $crate::__private::Cell<usize> =
+                        $crate::__private::Cell::new(0);
+                }
+
+                let __ez_guard = $crate::guard::Guard::enter(&__EZ_DEPTH);
+
+                #[allow(unused_macros)]
+                macro_rules! depth {
+                    () => {
+                        __ez_guard.depth()
+                    };
+                }
+
+                if __ez_guard.depth() > 1 {
+                    return orig!($($arg),*);
+                }
+
+                $body
+            }
+        }
+    };
+
     {
         @dollar($dollar:tt)
 
@@ -154,25 +784,65 @@ macro_rules! local_trampoline_hook {
                 $crate::local::trampoline::Hook::new(__ez_hook::$name)
             };
 
+            #[allow(dead_code)]
+            pub unsafe fn set_follow_thunks(follow: bool) {
+                __ez_HOOK.set_follow_thunks(follow)
+            }
+
             #[allow(dead_code)]
             pub unsafe fn set_trampoline(trampoline: &'static mut [u8; 24]) {
                 __ez_HOOK.set_trampoline(trampoline)
             }
 
+            #[allow(dead_code)]
+            pub unsafe fn set_stub(
+                stub: &'static mut [u8; 24],
+                flag: &'static core::sync::atomic::AtomicU8,
+            ) {
+                __ez_HOOK.set_stub(stub, flag)
+            }
+
+            #[allow(dead_code)]
             pub unsafe fn hook(target: __ez_Func) {
                 __ez_HOOK.hook(target)
             }
 
+            $crate::__ez_hook_symbol! {}
+
+            $crate::__ez_preload! {}
+
             #[allow(dead_code)]
             pub unsafe fn unhook() {
                 __ez_HOOK.unhook()
             }
 
+            $crate::__ez_reclaim! {}
+
             #[allow(dead_code)]
             pub unsafe fn toggle() {
                 __ez_HOOK.toggle()
             }
 
+            #[allow(dead_code)]
+            pub unsafe fn enable() {
+                __ez_HOOK.enable()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn disable() {
+                __ez_HOOK.disable()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn is_installed() -> bool {
+                __ez_HOOK.is_installed()
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn is_enabled() -> bool {
+                __ez_HOOK.is_enabled()
+            }
+
             #[allow(dead_code)]
             pub unsafe fn target() -> __ez_Func {
                 __ez_HOOK.target()
@@ -182,6 +852,11 @@ macro_rules! local_trampoline_hook {
             pub unsafe fn trampoline() -> __ez_Func {
                 __ez_HOOK.trampoline()
             }
+
+            #[allow(dead_code)]
+            pub unsafe fn info() -> Option<$crate::HookInfo> {
+                __ez_HOOK.info()
+            }
         }
     };
 
@@ -194,6 +869,8 @@ mod tests {
     use super::*;
     use crate::util;
 
+    use core::convert::TryInto;
+
     #[inline(never)]
     fn square(x: i32) -> i32 {
         util::black_box(x * x)
@@ -203,6 +880,10 @@ mod tests {
         x
     }
 
+    fn negate(x: i32) -> i32 {
+        -x
+    }
+
     local_trampoline_hook! {
         fn add_one_before(x: i32) -> i32 {
             orig!(x + 1)
@@ -283,4 +964,524 @@ mod tests {
             assert_eq!(square(5), 25);
         }
     }
+
+    #[test]
+    fn state() {
+        let trampoline = setup();
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(identity) };
+
+        assert_eq!(unsafe { hook.try_hook(square) }, Err(Error::NoTrampoline));
+
+        unsafe { hook.set_trampoline(trampoline) };
+        unsafe { hook.hook(square) };
+
+        assert!(hook.is_installed());
+        assert!(!hook.is_enabled());
+        assert_eq!(
+            unsafe { hook.try_hook(square) },
+            Err(Error::AlreadyInstalled)
+        );
+
+        let info = unsafe { hook.info() }.unwrap();
+        assert_eq!(info.target, square as usize);
+        assert_eq!(info.detour, identity as usize);
+        assert_eq!(info.patched[0], 0xE9);
+        assert!(info.stolen >= 1);
+        assert!(info.trampoline_len >= 10);
+
+        unsafe { hook.enable() };
+
+        assert_eq!(unsafe { hook.try_unhook() }, Err(Error::Enabled));
+        assert_eq!(unsafe { hook.info() }, Some(info));
+        assert_eq!(unsafe { hook.trampoline() }(4), 16);
+
+        unsafe { hook.disable() };
+        unsafe { hook.unhook() };
+
+        assert!(!hook.is_installed());
+        assert_eq!(unsafe { hook.try_toggle() }, Err(Error::NotInstalled));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    core::arch::global_asm!(
+        ".pushsection .text",
+        ".p2align 4",
+        "ezhook_trampoline_relative:",
+        "mov eax, dword ptr [rip + ezhook_trampoline_value]",
+        "ret",
+        ".popsection",
+        ".pushsection .data",
+        ".p2align 2",
+        "ezhook_trampoline_value:",
+        ".long 42",
+        ".popsection",
+        ".pushsection .data.rel.ro, \"aw\"",
+        ".p2align 3",
+        ".globl ezhook_trampoline_table",
+        ".hidden ezhook_trampoline_table",
+        "ezhook_trampoline_table:",
+        ".quad ezhook_trampoline_relative",
+        ".popsection",
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    extern "C" {
+        static ezhook_trampoline_table: [extern "C" fn() -> i32; 1];
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn relative() {
+        extern "C" fn zero() -> i32 {
+            0
+        }
+
+        let [relative] = unsafe { ezhook_trampoline_table };
+
+        util::unprotect(relative as _, 6);
+        let trampoline = util::allocate(relative as _, 24).try_into().unwrap();
+
+        let mut hook = unsafe { Hook::<extern "C" fn() -> i32>::new(zero) };
+        unsafe { hook.set_trampoline(trampoline) };
+        unsafe { hook.hook(relative) };
+
+        assert_eq!(unsafe { hook.trampoline() }(), 42);
+
+        unsafe { hook.enable() };
+
+        assert_eq!(relative(), 0);
+        assert_eq!(unsafe { hook.trampoline() }(), 42);
+
+        unsafe { hook.disable() };
+        unsafe { hook.unhook() };
+
+        assert_eq!(relative(), 42);
+    }
+
+    #[test]
+    fn set_detour() {
+        let trampoline = setup();
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(identity) };
+        unsafe { hook.set_trampoline(trampoline) };
+
+        unsafe { hook.hook(square) };
+        unsafe { hook.enable() };
+
+        assert_eq!(square(4), 4);
+
+        unsafe { hook.set_detour(negate) };
+
+        assert!(unsafe { hook.target() } == square);
+        assert_eq!(square(4), -4);
+        assert_eq!(unsafe { hook.trampoline() }(4), 16);
+
+        unsafe { hook.disable() };
+        unsafe { hook.set_detour(identity) };
+
+        assert_eq!(square(4), 16);
+
+        unsafe { hook.enable() };
+
+        assert_eq!(square(4), 4);
+        assert_eq!(unsafe { hook.info() }.unwrap().detour, identity as usize);
+
+        unsafe { hook.disable() };
+        unsafe { hook.unhook() };
+
+        assert_eq!(square(4), 16);
+    }
+
+    #[test]
+    fn moved() {
+        let trampoline = setup();
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(identity) };
+        unsafe { hook.set_trampoline(trampoline) };
+        unsafe { hook.hook(square) };
+
+        let mut moved = [unsafe { Hook::<fn(i32) -> i32>::new(identity) }, hook];
+
+        assert!(unsafe { moved[1].target() } == square);
+
+        unsafe { moved[1].enable() };
+
+        assert_eq!(square(4), 4);
+        assert_eq!(unsafe { moved[1].trampoline() }(4), 16);
+
+        unsafe { moved[1].disable() };
+        unsafe { moved[1].unhook() };
+
+        assert_eq!(square(4), 16);
+    }
+
+    #[test]
+    fn follow_thunks() {
+        let trampoline = setup();
+
+        let thunk = util::allocate(square as _, 5);
+        let offset = square as usize as isize - thunk.as_ptr() as isize - 5;
+        thunk[0] = 0xE9;
+        thunk[1..].copy_from_slice(&(offset as i32).to_ne_bytes());
+
+        let thunk: fn(i32) -> i32 = unsafe { FnPtr::from_addr(thunk.as_ptr() as usize) };
+        assert_eq!(thunk(4), 16);
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(identity) };
+        unsafe { hook.set_trampoline(trampoline) };
+        hook.set_follow_thunks(true);
+
+        unsafe { hook.hook(thunk) };
+
+        assert!(unsafe { hook.target() } == square);
+
+        unsafe { hook.enable() };
+
+        assert_eq!(thunk(4), 4);
+        assert_eq!(square(4), 4);
+        assert_eq!(unsafe { hook.trampoline() }(4), 16);
+
+        unsafe { hook.disable() };
+        unsafe { hook.unhook() };
+
+        assert_eq!(thunk(4), 16);
+    }
+
+    #[cfg(feature = "std")]
+    #[inline(never)]
+    fn quad(x: i32) -> i32 {
+        util::black_box(x * x)
+    }
+
+    #[cfg(feature = "std")]
+    local_trampoline_hook! {
+        #[guard]
+        fn double(x: i32) -> i32 {
+            assert_eq!(depth!(), 1);
+            quad(x) + orig!(x)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn guard() {
+        util::unprotect(quad as _, 5);
+        let trampoline = util::allocate(quad as _, 24).try_into().unwrap();
+
+        unsafe { double::set_trampoline(trampoline) };
+        unsafe { double::hook(quad) };
+        unsafe { double::enable() };
+
+        assert_eq!(quad(4), 32);
+        assert_eq!(quad(4), 32);
+
+        unsafe { double::disable() };
+        unsafe { double::unhook() };
+
+        assert_eq!(quad(4), 16);
+    }
+
+    #[cfg(all(feature = "filter", target_os = "linux"))]
+    #[inline(never)]
+    fn halve(x: i32) -> i32 {
+        util::black_box(x / 2)
+    }
+
+    #[cfg(all(feature = "filter", target_os = "linux"))]
+    static WORKERS: crate::filter::Filter = crate::filter::Filter::new();
+
+    #[cfg(all(feature = "filter", target_os = "linux"))]
+    local_trampoline_hook! {
+        #[filter(WORKERS)]
+        fn negate_filtered(x: i32) -> i32 {
+            -orig!(x)
+        }
+    }
+
+    #[cfg(all(feature = "filter", target_os = "linux"))]
+    #[test]
+    fn filter() {
+        extern crate std;
+
+        use std::thread;
+
+        util::unprotect(halve as _, 5);
+        let trampoline = util::allocate(halve as _, 24).try_into().unwrap();
+
+        unsafe { negate_filtered::set_trampoline(trampoline) };
+
+        unsafe { negate_filtered::hook(halve) };
+        unsafe { negate_filtered::enable() };
+
+        assert_eq!(halve(8), 4);
+
+        assert!(WORKERS.add_current());
+        assert_eq!(halve(8), -4);
+        assert_eq!(thread::spawn(|| halve(8)).join().unwrap(), 4);
+
+        assert!(WORKERS.remove_current());
+        assert_eq!(halve(8), 4);
+
+        unsafe { negate_filtered::disable() };
+        unsafe { negate_filtered::unhook() };
+
+        assert_eq!(halve(8), 4);
+    }
+
+    #[cfg(all(feature = "filter", target_os = "linux"))]
+    #[inline(never)]
+    fn third(x: i32) -> i32 {
+        util::black_box(x / 3)
+    }
+
+    #[cfg(all(feature = "filter", target_os = "linux"))]
+    static GUARDED: crate::filter::Filter = crate::filter::Filter::new();
+
+    #[cfg(all(feature = "filter", target_os = "linux"))]
+    local_trampoline_hook! {
+        #[filter(GUARDED)]
+        #[guard]
+        fn filtered_guarded(x: i32) -> i32 {
+            assert_eq!(depth!(), 1);
+            third(x) + orig!(x)
+        }
+    }
+
+    #[cfg(all(feature = "filter", target_os = "linux"))]
+    #[test]
+    fn filter_guard() {
+        util::unprotect(third as _, 5);
+        let trampoline = util::allocate(third as _, 24).try_into().unwrap();
+
+        unsafe { filtered_guarded::set_trampoline(trampoline) };
+        unsafe { filtered_guarded::hook(third) };
+        unsafe { filtered_guarded::enable() };
+
+        assert_eq!(third(9), 3);
+
+        assert!(GUARDED.add_current());
+        assert_eq!(third(9), 6);
+        assert!(GUARDED.remove_current());
+
+        unsafe { filtered_guarded::disable() };
+        unsafe { filtered_guarded::unhook() };
+
+        assert_eq!(third(9), 3);
+    }
+
+    #[inline(never)]
+    fn triple(x: i32) -> i32 {
+        util::black_box(x * 3)
+    }
+
+    static FLAG: AtomicU8 = AtomicU8::new(0);
+
+    #[test]
+    fn stub() {
+        util::unprotect(triple as _, 5);
+
+        let trampoline = util::allocate(triple as _, 24).try_into().unwrap();
+        let stub = util::allocate(triple as _, 24).try_into().unwrap();
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(identity) };
+        unsafe { hook.set_trampoline(trampoline) };
+        unsafe { hook.set_stub(stub, &FLAG) };
+
+        for _ in 0..2 {
+            unsafe { hook.hook(triple) };
+
+            let info = unsafe { hook.info() }.unwrap();
+            assert_eq!(info.patched[0], 0xE9);
+            assert_eq!(unsafe { *(triple as *const [u8; 5]) }, info.original);
+            assert_eq!(triple(4), 12);
+
+            unsafe { hook.toggle() };
+
+            assert_eq!(unsafe { hook.info() }.unwrap(), info);
+            assert_eq!(triple(4), 4);
+            assert_eq!(unsafe { hook.trampoline() }(4), 12);
+
+            unsafe { hook.set_detour(negate) };
+
+            assert_eq!(triple(4), -4);
+
+            unsafe { hook.toggle() };
+
+            assert_eq!(unsafe { *(triple as *const [u8; 5]) }, info.patched);
+            assert_eq!(triple(4), 12);
+
+            unsafe { hook.set_detour(identity) };
+            unsafe { hook.unhook() };
+
+            assert_eq!(unsafe { *(triple as *const [u8; 5]) }, info.original);
+            assert_eq!(triple(4), 12);
+        }
+    }
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    #[inline(never)]
+    fn cube(x: i32) -> i32 {
+        util::black_box(x * x * x)
+    }
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    static ENTERED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    static RELEASED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    static mut BLOCKING: Hook<fn(i32) -> i32> = unsafe { Hook::new(blocking) };
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    fn blocking(x: i32) -> i32 {
+        ENTERED.store(true, Ordering::SeqCst);
+        while !RELEASED.load(Ordering::SeqCst) {
+            core::hint::spin_loop();
+        }
+
+        let trampoline = unsafe { BLOCKING.trampoline() };
+        trampoline(x) + 1
+    }
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    #[test]
+    fn reclaim() {
+        extern crate std;
+
+        use crate::pool;
+        use std::thread;
+
+        util::unprotect(cube as _, 5);
+
+        let hook = unsafe { &mut BLOCKING };
+
+        for _ in 0..2 {
+            ENTERED.store(false, Ordering::SeqCst);
+            RELEASED.store(false, Ordering::SeqCst);
+
+            unsafe { hook.hook_allocated_stub(cube) };
+            unsafe { hook.enable() };
+
+            let trampoline = unsafe { hook.info() }.unwrap().trampoline;
+            assert!(pool::is_allocated(trampoline));
+            assert!(hook.is_quiescent());
+
+            let thread = thread::spawn(|| cube(3));
+
+            while !ENTERED.load(Ordering::SeqCst) {
+                core::hint::spin_loop();
+            }
+
+            assert_eq!(hook.in_flight(), 1);
+
+            unsafe { hook.disable() };
+            unsafe { hook.unhook() };
+
+            assert_eq!(cube(3), 27);
+            assert_eq!(unsafe { hook.try_free() }, Err(Error::ThreadInPatch));
+            assert!(pool::is_allocated(trampoline));
+
+            RELEASED.store(true, Ordering::SeqCst);
+
+            assert_eq!(thread.join().unwrap(), 28);
+            assert!(hook.is_quiescent());
+
+            unsafe { hook.unhook_and_free() };
+
+            assert!(!pool::is_allocated(trampoline));
+            assert_eq!(unsafe { hook.try_hook(cube) }, Err(Error::NoTrampoline));
+        }
+    }
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    #[inline(never)]
+    fn quintuple(x: i32) -> i32 {
+        util::black_box(x * 5)
+    }
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    local_trampoline_hook! {
+        fn counted(x: i32) -> i32 {
+            assert_eq!(unsafe { super::in_flight() }, 1);
+            orig!(x) + 1
+        }
+    }
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    #[test]
+    fn reclaim_macro() {
+        util::unprotect(quintuple as _, 5);
+
+        for _ in 0..2 {
+            unsafe { counted::hook_allocated_stub(quintuple) };
+            unsafe { counted::enable() };
+
+            assert_eq!(quintuple(4), 21);
+            assert_eq!(unsafe { counted::in_flight() }, 0);
+
+            unsafe { counted::unhook_and_free() };
+
+            assert!(!unsafe { counted::is_installed() });
+            assert_eq!(quintuple(4), 20);
+        }
+    }
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    static OPENED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    static WAITING: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    #[inline(never)]
+    fn waiting(x: i32) -> i32 {
+        WAITING.store(true, Ordering::SeqCst);
+        while !OPENED.load(Ordering::SeqCst) {
+            core::hint::spin_loop();
+        }
+
+        util::black_box(x)
+    }
+
+    #[cfg(all(feature = "reclaim", target_os = "linux"))]
+    #[test]
+    fn reclaim_disabled() {
+        extern crate std;
+
+        use crate::pool;
+        use std::thread;
+
+        util::unprotect(waiting as _, 5);
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(negate) };
+
+        unsafe { hook.hook_allocated_stub(waiting) };
+        unsafe { hook.enable() };
+        unsafe { hook.disable() };
+
+        let trampoline = unsafe { hook.info() }.unwrap().trampoline;
+        let thread = thread::spawn(|| waiting(3));
+
+        while !WAITING.load(Ordering::SeqCst) {
+            core::hint::spin_loop();
+        }
+
+        assert_eq!(hook.in_flight(), 1);
+
+        unsafe { hook.unhook() };
+
+        assert_eq!(unsafe { hook.try_free() }, Err(Error::ThreadInPatch));
+        assert!(pool::is_allocated(trampoline));
+
+        OPENED.store(true, Ordering::SeqCst);
+
+        assert_eq!(thread.join().unwrap(), 3);
+        assert!(hook.is_quiescent());
+
+        unsafe { hook.unhook_and_free() };
+
+        assert!(!pool::is_allocated(trampoline));
+    }
 }
@@ -0,0 +1,263 @@
+use core::{ffi::CStr, mem, slice};
+
+use libc::{
+    c_char, c_int, c_void, dl_iterate_phdr, dl_phdr_info, size_t, PF_X, PT_DYNAMIC, PT_LOAD,
+};
+
+#[cfg(target_pointer_width = "32")]
+use libc::{Elf32_Phdr as Phdr, Elf32_Sym as Sym};
+
+#[cfg(target_pointer_width = "64")]
+use libc::{Elf64_Phdr as Phdr, Elf64_Sym as Sym};
+
+const DT_NULL: usize = 0;
+const DT_PLTRELSZ: usize = 2;
+const DT_STRTAB: usize = 5;
+const DT_SYMTAB: usize = 6;
+const DT_JMPREL: usize = 23;
+
+#[cfg(target_pointer_width = "32")]
+const RELOCATION: usize = 2;
+
+#[cfg(target_pointer_width = "64")]
+const RELOCATION: usize = 3;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Module {
+    base: usize,
+    name: *const libc::c_char,
+    phdr: *const Phdr,
+    phnum: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub address: usize,
+    pub len: usize,
+    pub flags: u32,
+}
+
+impl Segment {
+    pub fn is_executable(&self) -> bool {
+        self.flags & PF_X != 0
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        self.address <= address && address < self.address + self.len
+    }
+}
+
+impl Module {
+    pub fn each(mut f: impl FnMut(&Module) -> bool) {
+        unsafe extern "C" fn callback(
+            info: *mut dl_phdr_info,
+            _: size_t,
+            data: *mut c_void,
+        ) -> c_int {
+            let f = &mut *(data as *mut &mut dyn FnMut(&Module) -> bool);
+            let info = &*info;
+
+            let module = Module {
+                base: info.dlpi_addr as usize,
+                name: info.dlpi_name,
+                phdr: info.dlpi_phdr,
+                phnum: info.dlpi_phnum as usize,
+            };
+
+            f(&module) as c_int
+        }
+
+        let mut f: &mut dyn FnMut(&Module) -> bool = &mut f;
+        unsafe { dl_iterate_phdr(Some(callback), &mut f as *mut _ as *mut c_void) };
+    }
+
+    pub fn find(name: &str) -> Option<Module> {
+        let mut found = None;
+
+        Module::each(|module| {
+            if module.matches(name) {
+                found = Some(*module);
+            }
+
+            found.is_some()
+        });
+
+        found
+    }
+
+    pub fn containing(address: usize) -> Option<Module> {
+        let mut found = None;
+
+        Module::each(|module| {
+            if module.segments().any(|segment| segment.contains(address)) {
+                found = Some(*module);
+            }
+
+            found.is_some()
+        });
+
+        found
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn name(&self) -> &[u8] {
+        if self.name.is_null() {
+            return &[];
+        }
+
+        unsafe { CStr::from_ptr(self.name) }.to_bytes()
+    }
+
+    pub fn is_main(&self) -> bool {
+        self.name().is_empty()
+    }
+
+    pub fn path(&self) -> &CStr {
+        if self.is_main() {
+            return unsafe { CStr::from_bytes_with_nul_unchecked(b"/proc/self/exe\0") };
+        }
+
+        unsafe { CStr::from_ptr(self.name) }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let path = self.name();
+
+        if name.is_empty() {
+            return path.is_empty();
+        }
+
+        let file = match path.iter().rposition(|&byte| byte == b'/') {
+            Some(slash) => &path[slash + 1..],
+            None => path,
+        };
+
+        let name = name.as_bytes();
+
+        path == name
+            || file == name
+            || file.starts_with(name) && matches!(file[name.len()], b'.' | b'-')
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + '_ {
+        let phdrs = unsafe { slice::from_raw_parts(self.phdr, self.phnum) };
+
+        phdrs
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .map(move |phdr| Segment {
+                address: self.base + phdr.p_vaddr as usize,
+                len: phdr.p_memsz as usize,
+                flags: phdr.p_flags,
+            })
+    }
+
+    pub fn executable(&self) -> impl Iterator<Item = Segment> + '_ {
+        self.segments().filter(Segment::is_executable)
+    }
+
+    pub unsafe fn import(&self, slot: usize) -> Option<&CStr> {
+        let phdrs = slice::from_raw_parts(self.phdr, self.phnum);
+        let dynamic = phdrs.iter().find(|phdr| phdr.p_type == PT_DYNAMIC)?;
+
+        let mut entry = (self.base + dynamic.p_vaddr as usize) as *const [usize; 2];
+        let (mut relocations, mut size, mut strings, mut symbols) = (0, 0, 0, 0);
+
+        loop {
+            match *entry {
+                [DT_NULL, _] => break,
+                [DT_PLTRELSZ, value] => size = value,
+                [DT_STRTAB, value] => strings = self.pointer(value),
+                [DT_SYMTAB, value] => symbols = self.pointer(value),
+                [DT_JMPREL, value] => relocations = self.pointer(value),
+                _ => {}
+            }
+
+            entry = entry.add(1);
+        }
+
+        if relocations == 0 || strings == 0 || symbols == 0 {
+            return None;
+        }
+
+        let relocations =
+            slice::from_raw_parts(relocations as *const usize, size / mem::size_of::<usize>());
+
+        let relocation = relocations
+            .chunks_exact(RELOCATION)
+            .find(|relocation| self.base.wrapping_add(relocation[0]) == slot)?;
+
+        #[cfg(target_pointer_width = "32")]
+        let index = relocation[1] >> 8;
+
+        #[cfg(target_pointer_width = "64")]
+        let index = relocation[1] >> 32;
+
+        if index == 0 {
+            return None;
+        }
+
+        let symbol = &*(symbols as *const Sym).add(index);
+        Some(CStr::from_ptr(
+            (strings + symbol.st_name as usize) as *const c_char,
+        ))
+    }
+
+    fn pointer(&self, value: usize) -> usize {
+        if value < self.base {
+            self.base + value
+        } else {
+            value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[inline(never)]
+    fn local() {}
+
+    #[test]
+    fn find() {
+        let main = Module::find("").unwrap();
+        assert!(main.is_main());
+        assert!(main
+            .executable()
+            .any(|segment| segment.contains(local as usize)));
+        assert_eq!(
+            Module::containing(local as usize).unwrap().base(),
+            main.base()
+        );
+
+        let libc = Module::find("libc").unwrap();
+        assert!(libc.name().ends_with(b".so.6"));
+        assert!(libc.executable().next().is_some());
+
+        assert!(Module::find("ezhook-missing").is_none());
+    }
+
+    #[test]
+    fn import() {
+        let main = Module::find("").unwrap();
+        let finalize =
+            unsafe { libc::dlsym(libc::RTLD_DEFAULT, b"__cxa_finalize\0".as_ptr() as _) };
+
+        let found = main
+            .segments()
+            .filter(|segment| !segment.is_executable())
+            .flat_map(|segment| {
+                (segment.address..segment.address + segment.len).step_by(mem::size_of::<usize>())
+            })
+            .filter(|&slot| unsafe { *(slot as *const usize) } == finalize as usize)
+            .filter_map(|slot| unsafe { main.import(slot) })
+            .any(|name| name.to_bytes() == b"__cxa_finalize");
+
+        assert!(found);
+        assert!(unsafe { main.import(local as usize) }.is_none());
+    }
+}
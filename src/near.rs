@@ -0,0 +1,103 @@
+use crate::protect;
+
+use libc::{
+    mmap, munmap, MAP_ANONYMOUS, MAP_FAILED, MAP_FIXED_NOREPLACE, MAP_PRIVATE, PROT_EXEC,
+    PROT_READ, PROT_WRITE,
+};
+
+const MIN_ADDRESS: usize = 0x10000;
+const RANGE: usize = 0x7FFF_0000;
+
+unsafe fn map(address: usize, size: usize, flags: i32) -> Option<usize> {
+    let region = mmap(
+        address as _,
+        size,
+        PROT_READ | PROT_WRITE | PROT_EXEC,
+        MAP_PRIVATE | MAP_ANONYMOUS | flags,
+        -1,
+        0,
+    );
+
+    if region == MAP_FAILED {
+        return None;
+    }
+
+    if address != 0 && region as usize != address {
+        munmap(region, size);
+        return None;
+    }
+
+    Some(region as usize)
+}
+
+pub(crate) unsafe fn allocate(near: usize, size: usize) -> Option<usize> {
+    let size = (size + protect::page_size() - 1) & !(protect::page_size() - 1);
+
+    if cfg!(target_arch = "x86") {
+        return map(0, size, 0);
+    }
+
+    for _ in 0..4 {
+        let mut best = None;
+        let mut previous = MIN_ADDRESS;
+
+        protect::maps(|from, to, _| {
+            if from >= previous + size {
+                let candidate = protect::page_of(near).max(previous).min(from - size);
+
+                if candidate.abs_diff(near) < RANGE
+                    && best.is_none_or(|best: usize| candidate.abs_diff(near) < best.abs_diff(near))
+                {
+                    best = Some(candidate);
+                }
+            }
+
+            previous = previous.max(to);
+            false
+        });
+
+        match best {
+            Some(address) => {
+                if let Some(region) = map(address, size, MAP_FIXED_NOREPLACE) {
+                    return Some(region);
+                }
+            }
+            None => return None,
+        }
+    }
+
+    None
+}
+
+pub(crate) unsafe fn free(address: usize, size: usize) {
+    munmap(address as _, size);
+}
+
+#[cfg(any(
+    feature = "closure",
+    feature = "probe",
+    feature = "breakpoint",
+    feature = "page",
+    feature = "vdso",
+    feature = "deferred"
+))]
+pub(crate) unsafe fn jump(at: usize, to: usize) -> usize {
+    let code = at as *mut u8;
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let mut jump = [0u8; 14];
+        jump[0..6].copy_from_slice(&[0xFF, 0x25, 0x00, 0x00, 0x00, 0x00]);
+        jump[6..14].copy_from_slice(&(to as u64).to_ne_bytes());
+        core::ptr::copy_nonoverlapping(jump.as_ptr(), code, jump.len());
+        jump.len()
+    }
+
+    #[cfg(target_arch = "x86")]
+    {
+        let mut jump = [0xE9u8; 5];
+        jump[1..5].copy_from_slice(&(to.wrapping_sub(at + 5) as u32).to_ne_bytes());
+        core::ptr::copy_nonoverlapping(jump.as_ptr(), code, jump.len());
+        jump.len()
+    }
+}
@@ -0,0 +1,520 @@
+use crate::{
+    decode,
+    info::{ENABLED, INSTALLED},
+    near, protect,
+    signal::{self, Chain, REG_FLAGS, REG_IP},
+    Error, FnPtr, HookInfo, Patch,
+};
+
+#[cfg(feature = "registry")]
+use crate::registry;
+
+use core::{
+    hint,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use libc::{
+    c_int, c_void, mprotect, siginfo_t, PROT_EXEC, SIGSEGV, SIGTRAP, SI_KERNEL, TRAP_TRACE,
+};
+
+const MAX_HOOKS: usize = 64;
+const TRAP_FLAG: libc::greg_t = 0x100;
+
+struct Slot {
+    target: AtomicUsize,
+    detour: AtomicUsize,
+    page: AtomicUsize,
+    prot: AtomicUsize,
+    enabled: AtomicBool,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Slot = Slot {
+    target: AtomicUsize::new(0),
+    detour: AtomicUsize::new(0),
+    page: AtomicUsize::new(0),
+    prot: AtomicUsize::new(0),
+    enabled: AtomicBool::new(false),
+};
+
+static SLOTS: [Slot; MAX_HOOKS] = [EMPTY; MAX_HOOKS];
+static STEPPER: AtomicUsize = AtomicUsize::new(0);
+
+static SEGV: Chain = Chain::new();
+static TRAP: Chain = Chain::new();
+
+unsafe fn guard(page: usize, prot: usize, enabled: bool) -> bool {
+    let prot = if enabled {
+        prot as c_int & !PROT_EXEC
+    } else {
+        prot as c_int
+    };
+
+    mprotect(page as _, protect::page_size(), prot) == 0
+}
+
+unsafe fn guarded(page: usize) -> Option<&'static Slot> {
+    SLOTS.iter().find(|slot| {
+        slot.enabled.load(Ordering::Acquire) && slot.page.load(Ordering::Acquire) == page
+    })
+}
+
+unsafe extern "C" fn segv(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    let registers = signal::registers(context);
+    let ip = registers[REG_IP as usize] as usize;
+
+    if (*info).si_addr() as usize == ip {
+        for slot in &SLOTS {
+            if slot.enabled.load(Ordering::Acquire) && slot.target.load(Ordering::Acquire) == ip {
+                registers[REG_IP as usize] = slot.detour.load(Ordering::Acquire) as _;
+                return;
+            }
+        }
+
+        if let Some(slot) = guarded(protect::page_of(ip)) {
+            if STEPPER
+                .compare_exchange(
+                    0,
+                    libc::gettid() as usize,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_err()
+            {
+                while STEPPER.load(Ordering::Acquire) != 0 {
+                    hint::spin_loop();
+                }
+
+                return;
+            }
+
+            guard(
+                slot.page.load(Ordering::Acquire),
+                slot.prot.load(Ordering::Acquire),
+                false,
+            );
+            registers[REG_FLAGS as usize] |= TRAP_FLAG;
+            return;
+        }
+    }
+
+    SEGV.forward(signal, info, context);
+}
+
+unsafe extern "C" fn trap(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    let registers = signal::registers(context);
+
+    if (*info).si_code == TRAP_TRACE
+        && registers[REG_FLAGS as usize] & TRAP_FLAG != 0
+        && STEPPER.load(Ordering::Acquire) == libc::gettid() as usize
+    {
+        registers[REG_FLAGS as usize] &= !TRAP_FLAG;
+
+        for slot in &SLOTS {
+            if slot.enabled.load(Ordering::Acquire) {
+                guard(
+                    slot.page.load(Ordering::Acquire),
+                    slot.prot.load(Ordering::Acquire),
+                    true,
+                );
+            }
+        }
+
+        STEPPER.store(0, Ordering::Release);
+        return;
+    }
+
+    if (*info).si_code != SI_KERNEL {
+        if TRAP.forward(signal, info, context) {
+            libc::raise(signal);
+        }
+
+        return;
+    }
+
+    let ip = registers[REG_IP as usize] as usize;
+    if TRAP.forward(signal, info, context) {
+        signal::registers(context)[REG_IP as usize] = ip.wrapping_sub(1) as _;
+    }
+}
+
+#[cfg(feature = "registry")]
+unsafe fn restore(info: &HookInfo, enabled: bool) -> Result<(), Error> {
+    for slot in &SLOTS {
+        if slot.target.load(Ordering::Acquire) == info.target {
+            let page = slot.page.load(Ordering::Acquire);
+            slot.enabled.store(enabled, Ordering::Release);
+            guard(
+                page,
+                slot.prot.load(Ordering::Acquire),
+                enabled || guarded(page).is_some(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub struct Hook<T: 'static> {
+    detour: T,
+    target: usize,
+    trampoline: usize,
+    slot: usize,
+    stolen_len: u8,
+    state: u8,
+    #[cfg(feature = "registry")]
+    id: usize,
+}
+
+impl<T> Hook<T> {
+    pub const unsafe fn new(detour: T) -> Self {
+        Self {
+            detour,
+            target: 0,
+            trampoline: 0,
+            slot: 0,
+            stolen_len: 0,
+            state: 0,
+            #[cfg(feature = "registry")]
+            id: 0,
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.state & INSTALLED != 0
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state & ENABLED != 0
+    }
+}
+
+impl<T: FnPtr> Hook<T> {
+    pub unsafe fn try_set_detour(&mut self, detour: T) -> Result<(), Error> {
+        self.guarded(|hook, _| {
+            if hook.is_installed() {
+                SLOTS[hook.slot]
+                    .detour
+                    .store(detour.to_addr(), Ordering::Release);
+            }
+
+            hook.detour = detour;
+
+            Ok(())
+        })
+    }
+
+    pub unsafe fn set_detour(&mut self, detour: T) {
+        self.try_set_detour(detour).unwrap()
+    }
+
+    pub unsafe fn try_hook(&mut self, target: T) -> Result<(), Error> {
+        #[cfg(feature = "registry")]
+        registry::check()?;
+
+        if self.is_installed() {
+            return Err(Error::AlreadyInstalled);
+        }
+
+        SEGV.install(SIGSEGV, segv)?;
+        TRAP.install(SIGTRAP, trap)?;
+
+        let target = target.to_addr();
+        let page = protect::page_of(target);
+        let prot = protect::protection(target).ok_or(Error::ProtectFailed)?;
+
+        let trampoline =
+            near::allocate(target, protect::page_size()).ok_or(Error::AllocationFailed)?;
+
+        let len = match decode::relocate(target, trampoline) {
+            Ok(len) => len,
+            Err(error) => {
+                near::free(trampoline, protect::page_size());
+                return Err(error);
+            }
+        };
+
+        let slot = match SLOTS.iter().position(|slot| {
+            slot.target
+                .compare_exchange(0, usize::MAX, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        }) {
+            Some(slot) => slot,
+            None => {
+                near::free(trampoline, protect::page_size());
+                return Err(Error::AllocationFailed);
+            }
+        };
+
+        let entry = &SLOTS[slot];
+        entry.detour.store(self.detour.to_addr(), Ordering::Release);
+        entry.page.store(page, Ordering::Release);
+        entry.prot.store(prot as usize, Ordering::Release);
+        entry.target.store(target, Ordering::Release);
+
+        self.target = target;
+        self.trampoline = trampoline;
+        self.slot = slot;
+        self.stolen_len = len as u8;
+        self.state = INSTALLED;
+
+        #[cfg(feature = "registry")]
+        match registry::join(self.info().unwrap(), false, restore) {
+            Ok(id) => self.id = id,
+            Err(error) => {
+                self.try_unhook()?;
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn hook(&mut self, target: T) {
+        self.try_hook(target).unwrap()
+    }
+
+    pub unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        SLOTS[self.slot].target.store(0, Ordering::Release);
+        near::free(self.trampoline, protect::page_size());
+
+        self.trampoline = 0;
+        self.state = 0;
+
+        #[cfg(feature = "registry")]
+        registry::leave(self.id);
+
+        Ok(())
+    }
+
+    pub unsafe fn unhook(&mut self) {
+        self.try_unhook().unwrap()
+    }
+
+    pub unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        self.guarded(|hook, killed| {
+            if killed && !hook.is_enabled() {
+                return Err(Error::Killed);
+            }
+
+            let slot = &SLOTS[hook.slot];
+            let page = slot.page.load(Ordering::Acquire);
+            let enabled = !hook.is_enabled();
+
+            slot.enabled.store(enabled, Ordering::Release);
+
+            let guarded = enabled || guarded(page).is_some();
+            if !guard(page, slot.prot.load(Ordering::Acquire), guarded) {
+                slot.enabled.store(!enabled, Ordering::Release);
+                return Err(Error::ProtectFailed);
+            }
+
+            hook.state ^= ENABLED;
+
+            Ok(())
+        })
+    }
+
+    pub unsafe fn toggle(&mut self) {
+        self.try_toggle().unwrap()
+    }
+
+    pub unsafe fn try_enable(&mut self) -> Result<(), Error> {
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn enable(&mut self) {
+        self.try_enable().unwrap()
+    }
+
+    pub unsafe fn try_disable(&mut self) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Err(Error::Disabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn disable(&mut self) {
+        self.try_disable().unwrap()
+    }
+
+    pub unsafe fn target(&self) -> T {
+        T::from_addr(self.target)
+    }
+
+    pub unsafe fn trampoline(&self) -> T {
+        T::from_addr(self.trampoline)
+    }
+
+    pub unsafe fn info(&self) -> Option<HookInfo> {
+        if !self.is_installed() {
+            return None;
+        }
+
+        let original = *(self.target as *const [u8; 5]);
+
+        Some(HookInfo {
+            target: self.target,
+            detour: self.detour.to_addr(),
+            trampoline: self.trampoline,
+            trampoline_len: self.stolen_len as usize,
+            original,
+            patched: original,
+            stolen: 1,
+        })
+    }
+
+    #[cfg(feature = "registry")]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        registry::guard(|guard| {
+            let result = f(self, guard.killed);
+
+            if let Some(info) = self.info() {
+                guard.update(self.id, info, self.is_enabled());
+            }
+
+            result
+        })
+    }
+
+    #[cfg(not(feature = "registry"))]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        f(self, false)
+    }
+}
+
+impl<T: FnPtr> Patch for Hook<T> {
+    type Target = T;
+
+    fn is_installed(&self) -> bool {
+        Hook::is_installed(self)
+    }
+
+    fn is_enabled(&self) -> bool {
+        Hook::is_enabled(self)
+    }
+
+    unsafe fn info(&self) -> Option<HookInfo> {
+        Hook::info(self)
+    }
+
+    unsafe fn try_hook(&mut self, target: T) -> Result<(), Error> {
+        Hook::try_hook(self, target)
+    }
+
+    unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        Hook::try_unhook(self)
+    }
+
+    unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        Hook::try_toggle(self)
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    extern crate std;
+
+    use std::{thread, vec::Vec};
+
+    core::arch::global_asm!(
+        ".pushsection .text",
+        ".balign 4096",
+        "ezhook_page_target:",
+        "mov eax, edi",
+        "add eax, 1",
+        "ret",
+        "ezhook_page_other:",
+        "lea eax, [rdi + rdi]",
+        "ret",
+        ".balign 4096",
+        ".popsection",
+        ".pushsection .data.rel.ro, \"aw\"",
+        ".p2align 3",
+        ".globl ezhook_page_table",
+        ".hidden ezhook_page_table",
+        "ezhook_page_table:",
+        ".quad ezhook_page_target",
+        ".quad ezhook_page_other",
+        ".popsection",
+    );
+
+    extern "C" {
+        static ezhook_page_table: [extern "C" fn(i32) -> i32; 2];
+    }
+
+    extern "C" fn negate(x: i32) -> i32 {
+        -x
+    }
+
+    #[test]
+    fn page() {
+        let [target, other] = unsafe { ezhook_page_table };
+
+        let mut hook = unsafe { Hook::<extern "C" fn(i32) -> i32>::new(negate) };
+
+        for _ in 0..2 {
+            unsafe { hook.hook(target) };
+
+            assert_eq!(target(4), 5);
+
+            unsafe { hook.enable() };
+
+            assert_eq!(
+                unsafe { hook.info() }.unwrap().patched[..3],
+                [0x89, 0xF8, 0x83]
+            );
+            assert_eq!(target(4), -4);
+            assert_eq!(other(4), 8);
+            assert_eq!(unsafe { hook.trampoline() }(4), 5);
+
+            let threads = (0..4)
+                .map(|_| {
+                    thread::spawn(move || {
+                        for _ in 0..1000 {
+                            assert_eq!(other(4), 8);
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for thread in threads {
+                thread.join().unwrap();
+            }
+
+            assert_eq!(target(4), -4);
+
+            unsafe { hook.disable() };
+
+            assert_eq!(target(4), 5);
+
+            unsafe { hook.unhook() };
+        }
+    }
+}
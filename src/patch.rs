@@ -0,0 +1,53 @@
+use crate::{Error, FnPtr, HookInfo};
+
+pub trait Patch {
+    type Target: FnPtr;
+
+    fn is_installed(&self) -> bool;
+    fn is_enabled(&self) -> bool;
+
+    unsafe fn info(&self) -> Option<HookInfo>;
+
+    unsafe fn try_hook(&mut self, target: Self::Target) -> Result<(), Error>;
+    unsafe fn try_unhook(&mut self) -> Result<(), Error>;
+    unsafe fn try_toggle(&mut self) -> Result<(), Error>;
+}
+
+#[cfg(all(feature = "transaction", target_os = "linux"))]
+pub(crate) trait DynPatch {
+    fn is_installed(&self) -> bool;
+    fn is_enabled(&self) -> bool;
+
+    unsafe fn info(&self) -> Option<HookInfo>;
+
+    unsafe fn try_hook(&mut self, target: usize) -> Result<(), Error>;
+    unsafe fn try_unhook(&mut self) -> Result<(), Error>;
+    unsafe fn try_toggle(&mut self) -> Result<(), Error>;
+}
+
+#[cfg(all(feature = "transaction", target_os = "linux"))]
+impl<P: Patch> DynPatch for P {
+    fn is_installed(&self) -> bool {
+        Patch::is_installed(self)
+    }
+
+    fn is_enabled(&self) -> bool {
+        Patch::is_enabled(self)
+    }
+
+    unsafe fn info(&self) -> Option<HookInfo> {
+        Patch::info(self)
+    }
+
+    unsafe fn try_hook(&mut self, target: usize) -> Result<(), Error> {
+        Patch::try_hook(self, P::Target::from_addr(target))
+    }
+
+    unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        Patch::try_unhook(self)
+    }
+
+    unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        Patch::try_toggle(self)
+    }
+}
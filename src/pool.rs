@@ -0,0 +1,121 @@
+use crate::{near, protect};
+
+use core::{
+    mem,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use libc::{PROT_READ, PROT_WRITE};
+
+const PAGES: usize = 64;
+const SLOTS: usize = 64;
+const SLOT: usize = 128;
+const SIZE: usize = SLOTS * SLOT;
+const DATA: usize = SLOTS * (1 + mem::size_of::<usize>());
+const RANGE: usize = 0x7FFF_0000 - SIZE;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicUsize = AtomicUsize::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE: AtomicU64 = AtomicU64::new(0);
+
+static BASES: [AtomicUsize; PAGES] = [EMPTY; PAGES];
+static USED: [AtomicU64; PAGES] = [FREE; PAGES];
+
+fn claim(index: usize) -> Option<usize> {
+    let mut used = USED[index].load(Ordering::Acquire);
+
+    while used != !0 {
+        let bit = (!used).trailing_zeros();
+
+        match USED[index].compare_exchange_weak(
+            used,
+            used | 1 << bit,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => return Some(BASES[index].load(Ordering::Acquire) + bit as usize * SLOT),
+            Err(current) => used = current,
+        }
+    }
+
+    None
+}
+
+fn data() -> usize {
+    (SIZE + protect::page_size() - 1) & !(protect::page_size() - 1)
+}
+
+fn reachable(base: usize, near: usize) -> bool {
+    base != 0 && (cfg!(target_arch = "x86") || base.abs_diff(near) < RANGE)
+}
+
+pub(crate) unsafe fn allocate(near: usize) -> Option<usize> {
+    for (index, base) in BASES.iter().enumerate() {
+        if reachable(base.load(Ordering::Acquire), near) {
+            if let Some(slot) = claim(index) {
+                return Some(slot);
+            }
+        }
+    }
+
+    let base = near::allocate(near, data() + DATA)?;
+
+    if !protect::protect(base + data(), PROT_READ | PROT_WRITE) {
+        near::free(base, data() + DATA);
+        return None;
+    }
+
+    for (index, slot) in BASES.iter().enumerate() {
+        if slot
+            .compare_exchange(0, base, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            return claim(index);
+        }
+    }
+
+    near::free(base, data() + DATA);
+
+    None
+}
+
+fn data_of(slot: usize) -> Option<(usize, usize)> {
+    BASES
+        .iter()
+        .map(|base| base.load(Ordering::Acquire))
+        .find(|&base| base != 0 && (base..base + SIZE).contains(&slot))
+        .map(|base| (base + data(), (slot - base) / SLOT))
+}
+
+pub(crate) fn flag(slot: usize) -> usize {
+    data_of(slot).map_or(0, |(data, index)| data + index)
+}
+
+pub(crate) fn counter(slot: usize) -> usize {
+    data_of(slot).map_or(0, |(data, index)| {
+        data + SLOTS + index * mem::size_of::<usize>()
+    })
+}
+
+pub(crate) fn free(slot: usize) {
+    for index in 0..PAGES {
+        let base = BASES[index].load(Ordering::Acquire);
+
+        if base != 0 && (base..base + SIZE).contains(&slot) {
+            USED[index].fetch_and(!(1 << ((slot - base) / SLOT)), Ordering::AcqRel);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn is_allocated(slot: usize) -> bool {
+    (0..PAGES).any(|index| {
+        let base = BASES[index].load(Ordering::Acquire);
+
+        base != 0
+            && (base..base + SIZE).contains(&slot)
+            && USED[index].load(Ordering::Acquire) & 1 << ((slot - base) / SLOT) != 0
+    })
+}
@@ -0,0 +1,213 @@
+use crate::{symbol, Error};
+
+use core::{
+    fmt::{self, Write},
+    mem, ptr, slice,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use libc::{mprotect, sysconf, _SC_PAGESIZE, PROT_EXEC, PROT_READ, PROT_WRITE, STDERR_FILENO};
+
+pub struct Entry {
+    pub library: &'static str,
+    pub name: &'static str,
+    pub install: unsafe fn(usize) -> Result<(), Error>,
+    pub uninstall: unsafe fn() -> Result<(), Error>,
+}
+
+extern "C" {
+    static __start_ezhook_preload: u8;
+    static __stop_ezhook_preload: u8;
+}
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+struct Stderr;
+
+impl Write for Stderr {
+    fn write_str(&mut self, message: &str) -> fmt::Result {
+        unsafe { libc::write(STDERR_FILENO, message.as_ptr() as _, message.len()) };
+        Ok(())
+    }
+}
+
+pub(crate) fn report(action: &str, name: &str, error: Error) {
+    let _ = writeln!(Stderr, "ezhook: failed to {} {}: {}", action, name, error);
+}
+
+pub(crate) unsafe fn unprotect(target: usize) -> Result<(), Error> {
+    let page = target & !(sysconf(_SC_PAGESIZE) as usize - 1);
+
+    if mprotect(
+        page as _,
+        target + 24 - page,
+        PROT_READ | PROT_WRITE | PROT_EXEC,
+    ) != 0
+    {
+        return Err(Error::ProtectFailed);
+    }
+
+    Ok(())
+}
+
+pub fn entries() -> &'static [Entry] {
+    unsafe {
+        let start = ptr::addr_of!(__start_ezhook_preload) as usize;
+        let stop = ptr::addr_of!(__stop_ezhook_preload) as usize;
+        let len = (stop - start) / mem::size_of::<Entry>();
+
+        slice::from_raw_parts(start as *const Entry, len)
+    }
+}
+
+unsafe fn install_entry(entry: &Entry) -> Result<(), Error> {
+    let target = symbol::resolve(entry.library, entry.name).ok_or(Error::SymbolNotFound)?;
+
+    unprotect(target)?;
+    (entry.install)(target)
+}
+
+pub unsafe fn install() {
+    if INSTALLED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    for entry in entries() {
+        if let Err(error) = install_entry(entry) {
+            report("hook", entry.name, error);
+        }
+    }
+}
+
+pub unsafe fn uninstall() {
+    if !INSTALLED.swap(false, Ordering::AcqRel) {
+        return;
+    }
+
+    for entry in entries().iter().rev() {
+        if let Err(error) = (entry.uninstall)() {
+            report("unhook", entry.name, error);
+        }
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ez_preload {
+    () => {
+        #[doc(hidden)]
+        #[allow(dead_code)]
+        pub unsafe fn __ez_install(target: usize) -> Result<(), $crate::Error> {
+            __ez_HOOK.try_hook(<__ez_Func as $crate::FnPtr>::from_addr(target))?;
+            __ez_HOOK.try_enable()
+        }
+
+        #[doc(hidden)]
+        #[allow(dead_code)]
+        pub unsafe fn __ez_uninstall() -> Result<(), $crate::Error> {
+            if !__ez_HOOK.is_installed() {
+                return Ok(());
+            }
+
+            if __ez_HOOK.is_enabled() {
+                __ez_HOOK.try_disable()?;
+            }
+
+            __ez_HOOK.try_unhook()
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! preload {
+    ($($($module:ident)::+ => ($library:expr, $name:expr)),* $(,)?) => {
+        const _: () = {
+            $(
+                const _: () = {
+                    #[used]
+                    #[link_section = "ezhook_preload"]
+                    static ENTRY: $crate::preload::Entry = $crate::preload::Entry {
+                        library: $library,
+                        name: $name,
+                        install: $($module)::+::__ez_install,
+                        uninstall: $($module)::+::__ez_uninstall,
+                    };
+                };
+            )*
+
+            #[used]
+            #[link_section = ".init_array"]
+            static INIT: extern "C" fn() = {
+                extern "C" fn init() {
+                    unsafe { $crate::preload::install() }
+                }
+
+                init
+            };
+
+            #[used]
+            #[link_section = ".fini_array"]
+            static FINI: extern "C" fn() = {
+                extern "C" fn fini() {
+                    unsafe { $crate::preload::uninstall() }
+                }
+
+                fini
+            };
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_swap_hook;
+
+    #[no_mangle]
+    #[inline(never)]
+    extern "C" fn ezhook_preload_test(x: i32) -> i32 {
+        crate::util::black_box(x * 5)
+    }
+
+    local_swap_hook! {
+        extern "C" fn preloaded(x: i32) -> i32 {
+            orig!(x) + 1
+        }
+    }
+
+    local_swap_hook! {
+        extern "C" fn missing(x: i32) -> i32 {
+            orig!(x)
+        }
+    }
+
+    preload! {
+        preloaded => ("", "ezhook_preload_test"),
+        self::missing => ("", "ezhook_preload_missing"),
+    }
+
+    #[test]
+    fn preload() {
+        assert!(entries()
+            .iter()
+            .any(|entry| entry.name == "ezhook_preload_test"));
+        assert!(entries()
+            .iter()
+            .any(|entry| entry.name == "ezhook_preload_missing"));
+
+        assert!(unsafe { preloaded::is_enabled() });
+        assert!(unsafe { !missing::is_installed() });
+        assert_eq!(ezhook_preload_test(2), 11);
+
+        unsafe { uninstall() };
+
+        assert!(unsafe { !preloaded::is_installed() });
+        assert_eq!(ezhook_preload_test(2), 10);
+
+        unsafe { install() };
+
+        assert_eq!(ezhook_preload_test(2), 11);
+
+        unsafe { uninstall() };
+    }
+}
@@ -0,0 +1,852 @@
+use crate::{
+    local::trampoline::Hook,
+    near,
+    protect::{self, Unprotected},
+    Error, FnPtr,
+};
+
+use core::{
+    arch::global_asm,
+    mem, ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use std::{
+    boxed::Box,
+    cell::{Cell, RefCell},
+    process,
+    sync::{Arc, Once},
+    vec::Vec,
+};
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct Arguments {
+    xmm: [[u64; 2]; 8],
+    rax: usize,
+    gpr: [usize; 6],
+}
+
+#[cfg(target_arch = "x86")]
+#[repr(C)]
+pub struct Arguments {
+    edx: usize,
+    ecx: usize,
+    eax: usize,
+    state: usize,
+}
+
+impl Arguments {
+    fn slot(&self) -> *mut usize {
+        (self as *const _ as usize + mem::size_of::<Self>()) as *mut usize
+    }
+
+    pub fn return_address(&self) -> usize {
+        unsafe { *self.slot() }
+    }
+
+    pub fn stack(&self) -> *const usize {
+        unsafe { self.slot().add(1) }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn arg(&self, index: usize) -> usize {
+        match index {
+            0..=5 => self.gpr[5 - index],
+            _ => unsafe { *self.stack().add(index - 6) },
+        }
+    }
+
+    #[cfg(target_arch = "x86")]
+    pub fn arg(&self, index: usize) -> usize {
+        unsafe { *self.stack().add(index) }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn float(&self, index: usize) -> f64 {
+        f64::from_bits(self.xmm[index][0])
+    }
+
+    #[cfg(target_arch = "x86")]
+    pub fn registers(&self) -> [usize; 3] {
+        [self.eax, self.ecx, self.edx]
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct Return {
+    xmm: [[u64; 2]; 2],
+    rdx: usize,
+    rax: usize,
+}
+
+#[cfg(target_arch = "x86")]
+#[repr(C)]
+pub struct Return {
+    edx: usize,
+    eax: usize,
+}
+
+impl Return {
+    fn slot(&self) -> usize {
+        self as *const _ as usize + mem::size_of::<Self>()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn value(&self) -> usize {
+        self.rax
+    }
+
+    #[cfg(target_arch = "x86")]
+    pub fn value(&self) -> usize {
+        self.eax
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn high(&self) -> usize {
+        self.rdx
+    }
+
+    #[cfg(target_arch = "x86")]
+    pub fn high(&self) -> usize {
+        self.edx
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn float(&self) -> f64 {
+        f64::from_bits(self.xmm[0][0])
+    }
+}
+
+type Entry = dyn Fn(&Arguments) -> usize + Send + Sync;
+type Exit = dyn Fn(&Return, usize) + Send + Sync;
+
+struct State {
+    entry: Box<Entry>,
+    exit: Box<Exit>,
+    trampoline: usize,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        unsafe { near::free(self.trampoline, protect::page_size()) };
+    }
+}
+
+struct Frame {
+    slot: usize,
+    ret: usize,
+    state: *const State,
+    cookie: usize,
+    silent: bool,
+}
+
+impl Frame {
+    unsafe fn release(self) {
+        drop(Arc::from_raw(self.state));
+    }
+}
+
+std::thread_local! {
+    static FRAMES: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+fn activate() -> bool {
+    ACTIVE
+        .try_with(|active| active.replace(true))
+        .unwrap_or(true)
+}
+
+fn deactivate(previous: bool) {
+    let _ = ACTIVE.try_with(|active| active.set(previous));
+}
+
+extern "C" {
+    fn __ezhook_probe_entry();
+    fn __ezhook_probe_exit();
+}
+
+unsafe fn pop_while(frames: &mut Vec<Frame>, f: impl Fn(&Frame) -> bool) {
+    while frames.last().is_some_and(&f) {
+        frames.pop().unwrap().release();
+    }
+}
+
+unsafe extern "C" fn enter(state: *const State, arguments: &Arguments) -> usize {
+    let slot = arguments.slot();
+    let trampoline = (*state).trampoline;
+    let active = activate();
+
+    let _ = FRAMES.try_with(|frames| {
+        pop_while(&mut frames.borrow_mut(), |frame| {
+            frame.slot <= slot as usize
+        })
+    });
+
+    let cookie = if active {
+        0
+    } else {
+        ((*state).entry)(arguments)
+    };
+
+    let pushed = FRAMES.try_with(|frames| {
+        Arc::increment_strong_count(state);
+
+        frames.borrow_mut().push(Frame {
+            slot: slot as usize,
+            ret: *slot,
+            state,
+            cookie,
+            silent: active,
+        });
+    });
+
+    if pushed.is_ok() {
+        *slot = __ezhook_probe_exit as *const () as usize;
+    }
+
+    deactivate(active);
+
+    trampoline
+}
+
+unsafe extern "C" fn leave(value: &Return) -> usize {
+    let slot = value.slot();
+    let active = activate();
+
+    let frame = FRAMES.try_with(|frames| {
+        let mut frames = frames.borrow_mut();
+        pop_while(&mut frames, |frame| frame.slot < slot);
+        frames.pop()
+    });
+
+    match frame {
+        Ok(Some(frame)) if frame.slot == slot => {
+            let ret = frame.ret;
+            if !frame.silent {
+                ((*frame.state).exit)(value, frame.cookie);
+            }
+            frame.release();
+            deactivate(active);
+            ret
+        }
+        _ => process::abort(),
+    }
+}
+
+pub unsafe fn restore() {
+    let _ = FRAMES.try_with(|frames| {
+        for frame in frames.borrow().iter() {
+            let slot = frame.slot as *mut usize;
+            if *slot == __ezhook_probe_exit as *const () as usize {
+                *slot = frame.ret;
+            }
+        }
+    });
+}
+
+unsafe fn rearm(cfa: usize) {
+    let _ = FRAMES.try_with(|frames| {
+        let mut frames = frames.borrow_mut();
+
+        pop_while(&mut frames, |frame| {
+            frame.slot + mem::size_of::<usize>() < cfa
+        });
+
+        for frame in frames.iter() {
+            let slot = frame.slot as *mut usize;
+            if *slot == frame.ret {
+                *slot = __ezhook_probe_exit as *const () as usize;
+            }
+        }
+    });
+}
+
+static mut RAISE: Hook<unsafe extern "C" fn(usize) -> i32> = unsafe { Hook::new(raise) };
+static mut FORCED: Hook<unsafe extern "C" fn(usize, usize, usize) -> i32> =
+    unsafe { Hook::new(forced) };
+static mut RESUME: Hook<unsafe extern "C" fn(usize)> = unsafe { Hook::new(resume) };
+static mut SET_IP: Hook<unsafe extern "C" fn(usize, usize)> = unsafe { Hook::new(set_ip) };
+static GET_CFA: AtomicUsize = AtomicUsize::new(0);
+
+unsafe extern "C" fn raise(exception: usize) -> i32 {
+    restore();
+    let result = (*ptr::addr_of!(RAISE)).trampoline()(exception);
+    rearm(0);
+    result
+}
+
+unsafe extern "C" fn forced(exception: usize, stop: usize, argument: usize) -> i32 {
+    restore();
+    let result = (*ptr::addr_of!(FORCED)).trampoline()(exception, stop, argument);
+    rearm(0);
+    result
+}
+
+unsafe extern "C" fn resume(exception: usize) {
+    restore();
+    (*ptr::addr_of!(RESUME)).trampoline()(exception)
+}
+
+unsafe extern "C" fn set_ip(context: usize, ip: usize) {
+    let get_cfa: unsafe extern "C" fn(usize) -> usize =
+        mem::transmute(GET_CFA.load(Ordering::Acquire));
+    rearm(get_cfa(context));
+    (*ptr::addr_of!(SET_IP)).trampoline()(context, ip)
+}
+
+unsafe fn hook_unwinder() {
+    hook_symbol(
+        &mut *ptr::addr_of_mut!(RAISE),
+        b"_Unwind_RaiseException\0",
+        raise as *const () as usize,
+    );
+    hook_symbol(
+        &mut *ptr::addr_of_mut!(FORCED),
+        b"_Unwind_ForcedUnwind\0",
+        forced as *const () as usize,
+    );
+    hook_symbol(
+        &mut *ptr::addr_of_mut!(RESUME),
+        b"_Unwind_Resume\0",
+        resume as *const () as usize,
+    );
+
+    let get_cfa = libc::dlsym(libc::RTLD_DEFAULT, b"_Unwind_GetCFA\0".as_ptr() as _);
+    if !get_cfa.is_null() {
+        GET_CFA.store(get_cfa as usize, Ordering::Release);
+        hook_symbol(
+            &mut *ptr::addr_of_mut!(SET_IP),
+            b"_Unwind_SetIP\0",
+            set_ip as *const () as usize,
+        );
+    }
+}
+
+unsafe fn hook_symbol<T: FnPtr>(hook: &mut Hook<T>, name: &[u8], detour: usize) {
+    let target = libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr() as _);
+    if target.is_null() {
+        return;
+    }
+
+    let target = target as usize;
+    let memory = match near::allocate(target, protect::page_size()) {
+        Some(memory) => memory,
+        None => return,
+    };
+
+    near::jump(memory + 32, detour);
+
+    hook.set_detour(FnPtr::from_addr(memory + 32));
+    hook.set_trampoline(&mut *(memory as *mut [u8; 24]));
+
+    let unprotected = [
+        Unprotected::new(protect::page_of(target)),
+        Unprotected::new(protect::page_of(target + 4)),
+    ];
+
+    if unprotected.iter().all(Option::is_some) && hook.try_hook(FnPtr::from_addr(target)).is_ok() {
+        let _ = hook.try_enable();
+    }
+}
+
+pub struct Probe {
+    hook: Hook<unsafe extern "C" fn()>,
+    state: Option<Arc<State>>,
+}
+
+impl Probe {
+    pub unsafe fn new<E, X>(target: usize, entry: E, exit: X) -> Result<Self, Error>
+    where
+        E: Fn(&Arguments) -> usize + Send + Sync + 'static,
+        X: Fn(&Return, usize) + Send + Sync + 'static,
+    {
+        static UNWINDER: Once = Once::new();
+        UNWINDER.call_once(|| hook_unwinder());
+
+        let memory = near::allocate(target, protect::page_size()).ok_or(Error::AllocationFailed)?;
+
+        let state = Arc::new(State {
+            entry: Box::new(entry),
+            exit: Box::new(exit),
+            trampoline: memory,
+        });
+
+        let thunk = memory + 32;
+
+        #[cfg(target_arch = "x86_64")]
+        let len = {
+            let code = thunk as *mut u8;
+            *code = 0x49;
+            *code.add(1) = 0xBB;
+            (code.add(2) as *mut usize).write_unaligned(Arc::as_ptr(&state) as usize);
+            10
+        };
+
+        #[cfg(target_arch = "x86")]
+        let len = {
+            let code = thunk as *mut u8;
+            *code = 0x68;
+            (code.add(1) as *mut usize).write_unaligned(Arc::as_ptr(&state) as usize);
+            5
+        };
+
+        near::jump(thunk + len, __ezhook_probe_entry as *const () as usize);
+
+        let mut probe = Self {
+            hook: Hook::new(FnPtr::from_addr(thunk)),
+            state: Some(state),
+        };
+
+        probe.hook.set_trampoline(&mut *(memory as *mut [u8; 24]));
+
+        if let Err(error) = probe.hook.try_hook(FnPtr::from_addr(target)) {
+            probe.state = None;
+            return Err(error);
+        }
+
+        Ok(probe)
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.hook.is_installed()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.hook.is_enabled()
+    }
+
+    #[cfg(feature = "trace")]
+    pub(crate) fn hook_mut(&mut self) -> &mut Hook<unsafe extern "C" fn()> {
+        &mut self.hook
+    }
+
+    pub unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        self.hook.try_unhook()?;
+
+        self.state = None;
+
+        Ok(())
+    }
+
+    pub unsafe fn unhook(&mut self) {
+        self.try_unhook().unwrap()
+    }
+
+    pub unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        self.hook.try_toggle()
+    }
+
+    pub unsafe fn toggle(&mut self) {
+        self.try_toggle().unwrap()
+    }
+
+    pub unsafe fn try_enable(&mut self) -> Result<(), Error> {
+        self.hook.try_enable()
+    }
+
+    pub unsafe fn enable(&mut self) {
+        self.try_enable().unwrap()
+    }
+
+    pub unsafe fn try_disable(&mut self) -> Result<(), Error> {
+        self.hook.try_disable()
+    }
+
+    pub unsafe fn disable(&mut self) {
+        self.try_disable().unwrap()
+    }
+}
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        if !self.hook.is_installed() {
+            return;
+        }
+
+        let disabled = !self.hook.is_enabled() || unsafe { self.hook.try_disable() }.is_ok();
+
+        if !disabled || unsafe { self.try_unhook() }.is_err() {
+            mem::forget(self.state.take());
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+global_asm!(
+    ".pushsection .text",
+    ".p2align 4",
+    ".globl __ezhook_probe_entry",
+    ".hidden __ezhook_probe_entry",
+    "__ezhook_probe_entry:",
+    "push rdi",
+    "push rsi",
+    "push rdx",
+    "push rcx",
+    "push r8",
+    "push r9",
+    "push rax",
+    "sub rsp, 128",
+    "movdqu [rsp], xmm0",
+    "movdqu [rsp + 16], xmm1",
+    "movdqu [rsp + 32], xmm2",
+    "movdqu [rsp + 48], xmm3",
+    "movdqu [rsp + 64], xmm4",
+    "movdqu [rsp + 80], xmm5",
+    "movdqu [rsp + 96], xmm6",
+    "movdqu [rsp + 112], xmm7",
+    "mov rdi, r11",
+    "mov rsi, rsp",
+    "call {enter}",
+    "mov r11, rax",
+    "movdqu xmm0, [rsp]",
+    "movdqu xmm1, [rsp + 16]",
+    "movdqu xmm2, [rsp + 32]",
+    "movdqu xmm3, [rsp + 48]",
+    "movdqu xmm4, [rsp + 64]",
+    "movdqu xmm5, [rsp + 80]",
+    "movdqu xmm6, [rsp + 96]",
+    "movdqu xmm7, [rsp + 112]",
+    "add rsp, 128",
+    "pop rax",
+    "pop r9",
+    "pop r8",
+    "pop rcx",
+    "pop rdx",
+    "pop rsi",
+    "pop rdi",
+    "jmp r11",
+    "",
+    ".p2align 4",
+    ".globl __ezhook_probe_exit",
+    ".hidden __ezhook_probe_exit",
+    "__ezhook_probe_exit:",
+    "sub rsp, 8",
+    "push rax",
+    "push rdx",
+    "sub rsp, 40",
+    "movdqu [rsp + 8], xmm0",
+    "movdqu [rsp + 24], xmm1",
+    "lea rdi, [rsp + 8]",
+    "call {leave}",
+    "mov [rsp + 56], rax",
+    "movdqu xmm0, [rsp + 8]",
+    "movdqu xmm1, [rsp + 24]",
+    "add rsp, 40",
+    "pop rdx",
+    "pop rax",
+    "ret",
+    ".popsection",
+    enter = sym enter,
+    leave = sym leave,
+);
+
+#[cfg(target_arch = "x86")]
+global_asm!(
+    ".pushsection .text",
+    ".p2align 4",
+    ".globl __ezhook_probe_entry",
+    ".hidden __ezhook_probe_entry",
+    "__ezhook_probe_entry:",
+    "push eax",
+    "push ecx",
+    "push edx",
+    "mov eax, esp",
+    "push eax",
+    "push dword ptr [eax + 12]",
+    "call {enter}",
+    "add esp, 8",
+    "mov [esp + 12], eax",
+    "pop edx",
+    "pop ecx",
+    "pop eax",
+    "ret",
+    "",
+    ".p2align 4",
+    ".globl __ezhook_probe_exit",
+    ".hidden __ezhook_probe_exit",
+    "__ezhook_probe_exit:",
+    "sub esp, 4",
+    "push eax",
+    "push edx",
+    "mov eax, esp",
+    "push eax",
+    "call {leave}",
+    "add esp, 4",
+    "mov [esp + 8], eax",
+    "pop edx",
+    "pop eax",
+    "ret",
+    ".popsection",
+    enter = sym enter,
+    leave = sym leave,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::panic;
+
+    #[inline(never)]
+    extern "C" fn add(a: usize, b: usize) -> usize {
+        util::black_box(a + b)
+    }
+
+    #[inline(never)]
+    extern "C" fn fib(n: usize) -> usize {
+        if n < 2 {
+            n
+        } else {
+            util::black_box(fib(n - 1) + fib(n - 2))
+        }
+    }
+
+    #[inline(never)]
+    extern "C-unwind" fn fail(x: usize) -> usize {
+        if util::black_box(x) > 0 {
+            panic!("fail");
+        }
+
+        x
+    }
+
+    #[inline(never)]
+    extern "C" fn sub(a: usize, b: usize) -> usize {
+        util::black_box(a - b)
+    }
+
+    #[inline(never)]
+    extern "C-unwind" fn throw(x: usize) -> usize {
+        if util::black_box(x) > 0 {
+            panic!("throw");
+        }
+
+        x
+    }
+
+    #[inline(never)]
+    extern "C" fn catch(x: usize) -> usize {
+        util::black_box(panic::catch_unwind(|| throw(x)).unwrap_or(0))
+    }
+
+    static mut DETACH: Option<Probe> = None;
+
+    #[inline(never)]
+    extern "C" fn detach(x: usize) -> usize {
+        if let Some(probe) = unsafe { (*ptr::addr_of_mut!(DETACH)).as_mut() } {
+            if !probe.is_installed() {
+                return util::black_box(x);
+            }
+
+            unsafe { probe.disable() };
+            unsafe { probe.unhook() };
+        }
+
+        util::black_box(x)
+    }
+
+    #[test]
+    fn probe() {
+        static ARGS: AtomicUsize = AtomicUsize::new(0);
+        static VALUE: AtomicUsize = AtomicUsize::new(0);
+
+        util::unprotect(add as _, 5);
+
+        let mut probe = unsafe {
+            Probe::new(
+                add as usize,
+                |arguments| {
+                    ARGS.store(arguments.arg(0) * 10 + arguments.arg(1), Ordering::SeqCst);
+                    42
+                },
+                |value, cookie| {
+                    assert_eq!(cookie, 42);
+                    VALUE.store(value.value(), Ordering::SeqCst);
+                },
+            )
+        }
+        .unwrap();
+
+        assert_eq!(add(1, 2), 3);
+        assert_eq!(VALUE.load(Ordering::SeqCst), 0);
+
+        unsafe { probe.enable() };
+
+        assert_eq!(add(3, 4), 7);
+        assert_eq!(ARGS.load(Ordering::SeqCst), 34);
+        assert_eq!(VALUE.load(Ordering::SeqCst), 7);
+
+        unsafe { probe.disable() };
+        unsafe { probe.unhook() };
+
+        assert_eq!(add(5, 6), 11);
+        assert_eq!(VALUE.load(Ordering::SeqCst), 7);
+    }
+
+    #[test]
+    fn drop_enabled() {
+        static EXITS: AtomicUsize = AtomicUsize::new(0);
+
+        util::unprotect(sub as _, 5);
+
+        let mut probe = unsafe {
+            Probe::new(
+                sub as *const () as usize,
+                |_| 0,
+                |_, _| {
+                    EXITS.fetch_add(1, Ordering::SeqCst);
+                },
+            )
+        }
+        .unwrap();
+
+        unsafe { probe.enable() };
+
+        assert_eq!(sub(7, 4), 3);
+        assert_eq!(EXITS.load(Ordering::SeqCst), 1);
+
+        drop(probe);
+
+        assert_eq!(sub(9, 4), 5);
+        assert_eq!(EXITS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn recursion() {
+        static ENTRIES: AtomicUsize = AtomicUsize::new(0);
+        static EXITS: AtomicUsize = AtomicUsize::new(0);
+
+        util::unprotect(fib as _, 5);
+
+        let mut probe = unsafe {
+            Probe::new(
+                fib as usize,
+                |arguments| {
+                    ENTRIES.fetch_add(1, Ordering::SeqCst);
+                    arguments.arg(0)
+                },
+                |value, n| {
+                    assert!(n >= 2 || value.value() == n);
+                    EXITS.fetch_add(1, Ordering::SeqCst);
+                },
+            )
+        }
+        .unwrap();
+
+        unsafe { probe.enable() };
+
+        assert_eq!(fib(10), 55);
+        assert_eq!(ENTRIES.load(Ordering::SeqCst), 177);
+        assert_eq!(EXITS.load(Ordering::SeqCst), 177);
+
+        unsafe { probe.disable() };
+        unsafe { probe.unhook() };
+    }
+
+    #[test]
+    fn unwind() {
+        static EXITS: AtomicUsize = AtomicUsize::new(0);
+
+        util::unprotect(fail as _, 5);
+
+        let mut probe = unsafe {
+            Probe::new(
+                fail as usize,
+                |_| 0,
+                |_, _| {
+                    EXITS.fetch_add(1, Ordering::SeqCst);
+                },
+            )
+        }
+        .unwrap();
+
+        unsafe { probe.enable() };
+
+        assert!(panic::catch_unwind(|| fail(1)).is_err());
+        assert_eq!(EXITS.load(Ordering::SeqCst), 0);
+
+        assert_eq!(fail(0), 0);
+        assert_eq!(EXITS.load(Ordering::SeqCst), 1);
+
+        unsafe { probe.disable() };
+        unsafe { probe.unhook() };
+    }
+
+    #[test]
+    fn unwind_caught() {
+        static EXITS: AtomicUsize = AtomicUsize::new(0);
+        static VALUE: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+        util::unprotect(catch as _, 5);
+        util::unprotect(throw as _, 5);
+
+        let mut outer = unsafe {
+            Probe::new(
+                catch as *const () as usize,
+                |_| 0,
+                |value, _| VALUE.store(value.value(), Ordering::SeqCst),
+            )
+        }
+        .unwrap();
+
+        let mut inner = unsafe {
+            Probe::new(
+                throw as *const () as usize,
+                |_| 0,
+                |_, _| {
+                    EXITS.fetch_add(1, Ordering::SeqCst);
+                },
+            )
+        }
+        .unwrap();
+
+        unsafe { outer.enable() };
+        unsafe { inner.enable() };
+
+        assert_eq!(catch(1), 0);
+        assert_eq!(VALUE.load(Ordering::SeqCst), 0);
+        assert_eq!(EXITS.load(Ordering::SeqCst), 0);
+
+        assert_eq!(catch(0), 0);
+        assert_eq!(EXITS.load(Ordering::SeqCst), 1);
+
+        unsafe { inner.disable() };
+        unsafe { inner.unhook() };
+        unsafe { outer.disable() };
+        unsafe { outer.unhook() };
+    }
+
+    #[test]
+    fn unhook_in_flight() {
+        static EXITS: AtomicUsize = AtomicUsize::new(0);
+
+        util::unprotect(detach as _, 5);
+
+        let probe = unsafe {
+            Probe::new(
+                detach as *const () as usize,
+                |_| 7,
+                |value, cookie| {
+                    assert_eq!(cookie, 7);
+                    assert_eq!(value.value(), 5);
+                    EXITS.fetch_add(1, Ordering::SeqCst);
+                },
+            )
+        }
+        .unwrap();
+
+        unsafe { *ptr::addr_of_mut!(DETACH) = Some(probe) };
+        unsafe { (*ptr::addr_of_mut!(DETACH)).as_mut().unwrap().enable() };
+
+        assert_eq!(detach(5), 5);
+        assert_eq!(EXITS.load(Ordering::SeqCst), 1);
+        assert!(!unsafe { (*ptr::addr_of!(DETACH)).as_ref().unwrap().is_installed() });
+
+        assert_eq!(detach(5), 5);
+        assert_eq!(EXITS.load(Ordering::SeqCst), 1);
+    }
+}
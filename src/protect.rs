@@ -0,0 +1,192 @@
+use libc::{
+    c_int, close, open, read, sysconf, _SC_PAGESIZE, O_CLOEXEC, O_RDONLY, PROT_EXEC, PROT_NONE,
+    PROT_READ, PROT_WRITE,
+};
+
+#[cfg(any(
+    feature = "transaction",
+    feature = "probe",
+    feature = "vdso",
+    feature = "deferred",
+    feature = "reclaim",
+    feature = "registry"
+))]
+use libc::mprotect;
+
+pub(crate) fn page_size() -> usize {
+    unsafe { sysconf(_SC_PAGESIZE) as usize }
+}
+
+pub(crate) fn page_of(address: usize) -> usize {
+    address & !(page_size() - 1)
+}
+
+pub(crate) unsafe fn maps(mut f: impl FnMut(usize, usize, c_int) -> bool) -> bool {
+    let fd = open(b"/proc/self/maps\0".as_ptr() as _, O_RDONLY | O_CLOEXEC);
+    if fd < 0 {
+        return false;
+    }
+
+    let mut buffer = [0u8; 4096];
+    let mut len = 0;
+    let mut skip = false;
+    let mut found = false;
+
+    'read: loop {
+        let count = read(fd, buffer[len..].as_mut_ptr() as _, buffer.len() - len);
+        if count <= 0 {
+            break;
+        }
+
+        len += count as usize;
+
+        let mut start = 0;
+        while let Some(newline) = buffer[start..len].iter().position(|&byte| byte == b'\n') {
+            let line = &buffer[start..start + newline];
+            start += newline + 1;
+
+            if skip {
+                skip = false;
+                continue;
+            }
+
+            if let Some((from, to, prot)) = parse(line) {
+                if f(from, to, prot) {
+                    found = true;
+                    break 'read;
+                }
+            }
+        }
+
+        if start == 0 && len == buffer.len() {
+            if let Some((from, to, prot)) = parse(&buffer[..len]) {
+                if f(from, to, prot) {
+                    found = true;
+                    break;
+                }
+            }
+
+            skip = true;
+            len = 0;
+        } else {
+            buffer.copy_within(start..len, 0);
+            len -= start;
+        }
+    }
+
+    close(fd);
+    found
+}
+
+fn parse(line: &[u8]) -> Option<(usize, usize, c_int)> {
+    let mut fields = line.split(|&byte| byte == b' ');
+
+    let mut range = fields.next()?.split(|&byte| byte == b'-');
+    let from = hex(range.next()?)?;
+    let to = hex(range.next()?)?;
+
+    let perms = fields.next()?;
+    if perms.len() < 3 {
+        return None;
+    }
+
+    let mut prot = PROT_NONE;
+    if perms[0] == b'r' {
+        prot |= PROT_READ;
+    }
+    if perms[1] == b'w' {
+        prot |= PROT_WRITE;
+    }
+    if perms[2] == b'x' {
+        prot |= PROT_EXEC;
+    }
+
+    Some((from, to, prot))
+}
+
+fn hex(digits: &[u8]) -> Option<usize> {
+    if digits.is_empty() {
+        return None;
+    }
+
+    digits.iter().try_fold(0usize, |value, &digit| {
+        let digit = (digit as char).to_digit(16)?;
+        Some(value << 4 | digit as usize)
+    })
+}
+
+#[cfg(any(
+    feature = "transaction",
+    feature = "probe",
+    feature = "page",
+    feature = "vdso",
+    feature = "registry"
+))]
+pub(crate) unsafe fn protection(address: usize) -> Option<c_int> {
+    let mut prot = None;
+
+    maps(|from, to, current| {
+        if from <= address && address < to {
+            prot = Some(current);
+        }
+
+        prot.is_some()
+    });
+
+    prot
+}
+
+#[cfg(any(
+    feature = "transaction",
+    feature = "probe",
+    feature = "vdso",
+    feature = "deferred",
+    feature = "reclaim",
+    feature = "registry"
+))]
+pub(crate) unsafe fn protect(page: usize, prot: c_int) -> bool {
+    mprotect(page as _, page_size(), prot) == 0
+}
+
+#[cfg(any(
+    feature = "transaction",
+    feature = "probe",
+    feature = "vdso",
+    feature = "registry"
+))]
+pub(crate) struct Unprotected {
+    page: usize,
+    prot: c_int,
+}
+
+#[cfg(any(
+    feature = "transaction",
+    feature = "probe",
+    feature = "vdso",
+    feature = "registry"
+))]
+impl Unprotected {
+    pub(crate) unsafe fn new(page: usize) -> Option<Self> {
+        let prot = protection(page)?;
+
+        if prot & PROT_WRITE == 0 && !protect(page, prot | PROT_WRITE) {
+            return None;
+        }
+
+        Some(Self { page, prot })
+    }
+}
+
+#[cfg(any(
+    feature = "transaction",
+    feature = "probe",
+    feature = "vdso",
+    feature = "registry"
+))]
+impl Drop for Unprotected {
+    fn drop(&mut self) {
+        if self.prot & PROT_WRITE == 0 {
+            unsafe { protect(self.page, self.prot) };
+        }
+    }
+}
@@ -0,0 +1,500 @@
+use crate::{Error, HookInfo};
+
+use core::{
+    cell::UnsafeCell,
+    hint,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+};
+
+const MAX_HOOKS: usize = 256;
+
+const ACTIVE: u8 = 0;
+const DISABLED: u8 = 1;
+const UNHOOKED: u8 = 2;
+
+#[derive(Clone, Copy)]
+struct Record {
+    id: usize,
+    info: HookInfo,
+    patched: bool,
+    restore: unsafe fn(&HookInfo, bool) -> Result<(), Error>,
+}
+
+struct Registry {
+    lock: AtomicBool,
+    records: UnsafeCell<[Option<Record>; MAX_HOOKS]>,
+}
+
+unsafe impl Sync for Registry {}
+
+static REGISTRY: Registry = Registry {
+    lock: AtomicBool::new(false),
+    records: UnsafeCell::new([None; MAX_HOOKS]),
+};
+
+static STATE: AtomicU8 = AtomicU8::new(ACTIVE);
+static NEXT: AtomicUsize = AtomicUsize::new(1);
+
+fn locked<R>(f: impl FnOnce(&mut [Option<Record>; MAX_HOOKS]) -> R) -> R {
+    while REGISTRY
+        .lock
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        hint::spin_loop();
+    }
+
+    let result = f(unsafe { &mut *REGISTRY.records.get() });
+
+    REGISTRY.lock.store(false, Ordering::Release);
+
+    result
+}
+
+pub(crate) fn check() -> Result<(), Error> {
+    if STATE.load(Ordering::Acquire) != ACTIVE {
+        return Err(Error::Killed);
+    }
+
+    Ok(())
+}
+
+fn installed(records: &[Option<Record>; MAX_HOOKS]) -> ([usize; MAX_HOOKS], usize) {
+    let mut order = [0; MAX_HOOKS];
+    let mut len = 0;
+
+    for (index, record) in records.iter().enumerate() {
+        if record.is_some() {
+            order[len] = index;
+            len += 1;
+        }
+    }
+
+    order[..len].sort_unstable_by_key(|&index| records[index].map(|record| record.id));
+
+    (order, len)
+}
+
+pub(crate) fn join(
+    info: HookInfo,
+    patched: bool,
+    restore: unsafe fn(&HookInfo, bool) -> Result<(), Error>,
+) -> Result<usize, Error> {
+    locked(|records| {
+        let state = STATE.load(Ordering::Acquire);
+
+        if state != ACTIVE {
+            let _ = unsafe { restore(&info, false) };
+        }
+
+        if state == UNHOOKED {
+            return Ok(0);
+        }
+
+        let index = records
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::RegistryFull)?;
+
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+
+        records[index] = Some(Record {
+            id,
+            info,
+            patched,
+            restore,
+        });
+
+        Ok(id)
+    })
+}
+
+pub(crate) struct Guard<'a> {
+    records: &'a mut [Option<Record>; MAX_HOOKS],
+    pub(crate) killed: bool,
+}
+
+impl Guard<'_> {
+    pub(crate) fn update(&mut self, id: usize, info: HookInfo, patched: bool) {
+        for record in self.records.iter_mut().flatten() {
+            if record.id == id {
+                record.info = info;
+                record.patched = patched;
+            }
+        }
+    }
+}
+
+pub(crate) fn guard<R>(f: impl FnOnce(&mut Guard) -> R) -> R {
+    locked(|records| {
+        f(&mut Guard {
+            records,
+            killed: STATE.load(Ordering::Acquire) != ACTIVE,
+        })
+    })
+}
+
+pub(crate) fn leave(id: usize) {
+    locked(|records| {
+        for record in records.iter_mut() {
+            if record.is_some_and(|record| record.id == id) {
+                *record = None;
+            }
+        }
+    })
+}
+
+pub fn hooks(mut f: impl FnMut(&HookInfo) -> bool) -> bool {
+    for index in 0..MAX_HOOKS {
+        if let Some(record) = locked(|records| records[index]) {
+            if f(&record.info) {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+pub fn count() -> usize {
+    locked(|records| records.iter().flatten().count())
+}
+
+pub fn is_disabled() -> bool {
+    STATE.load(Ordering::Acquire) != ACTIVE
+}
+
+pub unsafe fn try_disable_all() -> Result<(), Error> {
+    locked(|records| {
+        match STATE.load(Ordering::Acquire) {
+            ACTIVE => {}
+            DISABLED => return Err(Error::Disabled),
+            _ => return Err(Error::Killed),
+        }
+
+        let (order, len) = installed(records);
+
+        for &index in order[..len].iter().rev() {
+            if let Some(record) = records[index] {
+                let _ = (record.restore)(&record.info, false);
+            }
+        }
+
+        STATE.store(DISABLED, Ordering::Release);
+
+        Ok(())
+    })
+}
+
+pub unsafe fn disable_all() {
+    try_disable_all().unwrap()
+}
+
+pub unsafe fn try_enable_all() -> Result<(), Error> {
+    locked(|records| {
+        match STATE.load(Ordering::Acquire) {
+            DISABLED => {}
+            ACTIVE => return Err(Error::Enabled),
+            _ => return Err(Error::Killed),
+        }
+
+        let (order, len) = installed(records);
+
+        for &index in &order[..len] {
+            if let Some(record) = records[index].filter(|record| record.patched) {
+                let _ = (record.restore)(&record.info, true);
+            }
+        }
+
+        STATE.store(ACTIVE, Ordering::Release);
+
+        Ok(())
+    })
+}
+
+pub unsafe fn enable_all() {
+    try_enable_all().unwrap()
+}
+
+pub unsafe fn unhook_all() {
+    locked(|records| {
+        let (order, len) = installed(records);
+
+        for &index in order[..len].iter().rev() {
+            if let Some(record) = records[index].take() {
+                let _ = (record.restore)(&record.info, false);
+            }
+        }
+
+        STATE.store(UNHOOKED, Ordering::Release);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{local::swap::Hook, protect, util};
+
+    use libc::{mprotect, PROT_EXEC, PROT_READ};
+
+    #[inline(never)]
+    fn double(x: i32) -> i32 {
+        util::black_box(x * 2)
+    }
+
+    #[inline(never)]
+    fn triple(x: i32) -> i32 {
+        util::black_box(x * 3)
+    }
+
+    #[inline(never)]
+    fn quadruple(x: i32) -> i32 {
+        util::black_box(x * 4)
+    }
+
+    fn negate(x: i32) -> i32 {
+        -x
+    }
+
+    fn registered(target: fn(i32) -> i32) -> Option<HookInfo> {
+        let mut found = None;
+
+        hooks(|info| {
+            if info.target == target as usize {
+                found = Some(*info);
+            }
+
+            found.is_some()
+        });
+
+        found
+    }
+
+    #[test]
+    fn enumerate() {
+        util::unprotect(double as _, 5);
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(negate) };
+
+        for _ in 0..2 {
+            assert_eq!(registered(double), None);
+
+            unsafe { hook.hook(double) };
+
+            let info = registered(double).unwrap();
+            assert_eq!(Some(info), unsafe { hook.info() });
+            assert_eq!(info.detour, negate as usize);
+
+            unsafe { hook.enable() };
+
+            assert_eq!(registered(double), unsafe { hook.info() });
+            assert_eq!(double(4), -4);
+
+            unsafe { hook.disable() };
+            unsafe { hook.unhook() };
+
+            assert_eq!(registered(double), None);
+            assert_eq!(double(4), 8);
+        }
+    }
+
+    #[test]
+    fn kill_switch() {
+        if !util::isolated("registry::tests::kill_switch") {
+            return;
+        }
+
+        util::unprotect(triple as _, 5);
+        util::unprotect(quadruple as _, 5);
+
+        let mut enabled = unsafe { Hook::<fn(i32) -> i32>::new(negate) };
+        let mut disabled = unsafe { Hook::<fn(i32) -> i32>::new(negate) };
+
+        let before = count();
+
+        unsafe { enabled.hook(triple) };
+        unsafe { enabled.enable() };
+        unsafe { disabled.hook(quadruple) };
+
+        assert_eq!(count(), before + 2);
+        assert_eq!(triple(4), -4);
+        assert_eq!(quadruple(4), 16);
+
+        unsafe { disable_all() };
+
+        assert!(is_disabled());
+        assert_eq!(count(), before + 2);
+        assert_eq!(triple(4), 12);
+        assert_eq!(quadruple(4), 16);
+        assert_eq!(unsafe { disabled.try_enable() }, Err(Error::Killed));
+        assert_eq!(unsafe { try_disable_all() }, Err(Error::Disabled));
+
+        unsafe { enable_all() };
+
+        assert!(!is_disabled());
+        assert_eq!(triple(4), -4);
+        assert_eq!(quadruple(4), 16);
+        assert_eq!(unsafe { try_enable_all() }, Err(Error::Enabled));
+
+        unsafe { disable_all() };
+        unsafe { enabled.disable() };
+        unsafe { enable_all() };
+
+        assert_eq!(triple(4), 12);
+
+        unsafe { enabled.enable() };
+        unsafe { disabled.enable() };
+
+        assert_eq!(triple(4), -4);
+        assert_eq!(quadruple(4), -4);
+
+        unsafe { unhook_all() };
+
+        assert!(is_disabled());
+        assert_eq!(count(), 0);
+        assert_eq!(triple(4), 12);
+        assert_eq!(quadruple(4), 16);
+        assert_eq!(unsafe { enabled.try_enable() }, Err(Error::Enabled));
+        assert_eq!(unsafe { try_enable_all() }, Err(Error::Killed));
+
+        unsafe { enabled.disable() };
+        unsafe { enabled.unhook() };
+
+        assert_eq!(triple(4), 12);
+
+        let mut late = unsafe { Hook::<fn(i32) -> i32>::new(negate) };
+        assert_eq!(unsafe { late.try_hook(double) }, Err(Error::Killed));
+    }
+
+    #[inline(never)]
+    fn shared(x: i32) -> i32 {
+        util::black_box(x * 6)
+    }
+
+    fn increment(x: i32) -> i32 {
+        x + 1
+    }
+
+    #[test]
+    fn chained() {
+        if !util::isolated("registry::tests::chained") {
+            return;
+        }
+
+        util::unprotect(shared as _, 5);
+
+        let mut first = unsafe { Hook::<fn(i32) -> i32>::new(negate) };
+        let mut second = unsafe { Hook::<fn(i32) -> i32>::new(increment) };
+
+        let before = count();
+
+        unsafe { first.hook(shared) };
+        unsafe { first.enable() };
+        unsafe { second.hook(shared) };
+        unsafe { second.enable() };
+
+        assert_eq!(count(), before + 2);
+        assert_eq!(shared(2), 3);
+
+        unsafe { second.disable() };
+        unsafe { second.unhook() };
+
+        assert_eq!(count(), before + 1);
+        assert_eq!(shared(2), -2);
+
+        unsafe { second.hook(shared) };
+        unsafe { second.enable() };
+
+        unsafe { disable_all() };
+
+        assert_eq!(shared(2), 12);
+
+        unsafe { enable_all() };
+
+        assert_eq!(shared(2), 3);
+
+        unsafe { unhook_all() };
+
+        assert_eq!(count(), 0);
+        assert_eq!(shared(2), 12);
+    }
+
+    #[inline(never)]
+    fn septuple(x: i32) -> i32 {
+        util::black_box(x * 7)
+    }
+
+    #[test]
+    fn protected() {
+        if !util::isolated("registry::tests::protected") {
+            return;
+        }
+
+        util::unprotect(septuple as _, 5);
+
+        let mut hook = unsafe { Hook::<fn(i32) -> i32>::new(negate) };
+
+        unsafe { hook.hook(septuple) };
+        unsafe { hook.enable() };
+
+        assert_eq!(septuple(2), -2);
+
+        let target = septuple as *const () as usize;
+        let pages = [target, target + 4].map(protect::page_of);
+
+        for page in pages {
+            unsafe { mprotect(page as _, protect::page_size(), PROT_READ | PROT_EXEC) };
+        }
+
+        unsafe { disable_all() };
+
+        assert_eq!(septuple(2), 14);
+        assert_eq!(
+            unsafe { protect::protection(pages[0]) },
+            Some(PROT_READ | PROT_EXEC)
+        );
+
+        unsafe { enable_all() };
+
+        assert_eq!(septuple(2), -2);
+
+        unsafe { unhook_all() };
+
+        assert_eq!(septuple(2), 14);
+    }
+
+    #[inline(never)]
+    fn killing(x: i32) -> i32 {
+        let _ = unsafe { try_disable_all() };
+        util::black_box(x * 5)
+    }
+
+    crate::local_swap_hook! {
+        fn add_one(x: i32) -> i32 {
+            orig!(x) + 1
+        }
+    }
+
+    #[test]
+    fn kill_in_orig() {
+        if !util::isolated("registry::tests::kill_in_orig") {
+            return;
+        }
+
+        util::unprotect(killing as _, 5);
+
+        unsafe { add_one::hook(killing) };
+        unsafe { add_one::enable() };
+
+        assert_eq!(killing(4), 21);
+        assert!(is_disabled());
+        assert!(unsafe { add_one::is_enabled() });
+        assert_eq!(killing(4), 20);
+
+        unsafe { enable_all() };
+
+        assert_eq!(killing(4), 21);
+    }
+}
@@ -1,24 +1,24 @@
-use crate::{local::swap::Hook, util};
+use crate::{local::swap::Hook, FnPtr, Relative};
 
 use core::{mem, slice};
 
 #[doc(hidden)]
-pub unsafe fn len<T: Copy>(end: &'static Hook<T>, start: T) -> usize {
-    let start: usize = util::transmute(start);
-    end as *const _ as usize - start + mem::size_of_val(end)
+pub unsafe fn len<T: FnPtr>(end: &'static Hook<T, Relative>, start: T) -> usize {
+    end as *const _ as usize - start.to_addr() + mem::size_of_val(end)
 }
 
 #[doc(hidden)]
-pub unsafe fn copy_to<T: Copy>(
-    end: &'static Hook<T>,
+pub unsafe fn copy_to<T: FnPtr>(
+    end: &'static Hook<T, Relative>,
     start: T,
     dest: &'static mut [u8],
-) -> &'static mut Hook<T> {
+) -> &'static mut Hook<T, Relative> {
     let size = len(end, start);
-    dest.copy_from_slice(slice::from_raw_parts(util::transmute(start), size));
+    dest.copy_from_slice(slice::from_raw_parts(start.to_addr() as *const u8, size));
 
-    let remote = &mut *(dest[size - mem::size_of_val(end)..].as_mut_ptr() as *mut Hook<T>);
-    remote.set_detour(util::transmute(dest.as_ptr()));
+    let remote =
+        &mut *(dest[size - mem::size_of_val(end)..].as_mut_ptr() as *mut Hook<T, Relative>);
+    remote.set_detour(T::from_addr(dest.as_ptr() as usize));
     remote
 }
 
@@ -79,9 +79,9 @@ macro_rules! remote_swap_hook {
 
                 #[link_section = "ezhk,rem"]
                 #[allow(non_upper_case_globals)]
-                pub static mut __ez_HOOK: $crate::local::swap::Hook<super::__ez_Func> = unsafe {
-                    $crate::local::swap::Hook::new($name)
-                };
+                pub static mut __ez_HOOK:
+                    $crate::local::swap::Hook<super::__ez_Func, $crate::Relative> =
+                    unsafe { $crate::local::swap::Hook::new($name) };
             }
 
             #[allow(unused_imports)]
@@ -100,7 +100,7 @@ macro_rules! remote_swap_hook {
 
             pub unsafe fn copy_to(
                 dest: &'static mut [u8],
-            ) -> &mut $crate::local::swap::Hook<__ez_Func> {
+            ) -> &mut $crate::local::swap::Hook<__ez_Func, $crate::Relative> {
                 $crate::remote::swap::copy_to(&__ez_hook::__ez_HOOK, __ez_hook::$name, dest)
             }
         }
@@ -1,24 +1,24 @@
-use crate::{local::trampoline::Hook, util};
+use crate::{local::trampoline::Hook, FnPtr, Relative};
 
 use core::{convert::TryInto, mem, slice};
 
 #[doc(hidden)]
-pub unsafe fn len<T: Copy>(end: &'static Hook<T>, start: T) -> usize {
-    let start: usize = util::transmute(start);
-    end as *const _ as usize - start + mem::size_of_val(end) + 24
+pub unsafe fn len<T: FnPtr>(end: &'static Hook<T, Relative>, start: T) -> usize {
+    end as *const _ as usize - start.to_addr() + mem::size_of_val(end) + 24
 }
 
 #[doc(hidden)]
-pub unsafe fn copy_to<T: Copy>(
-    end: &'static Hook<T>,
+pub unsafe fn copy_to<T: FnPtr>(
+    end: &'static Hook<T, Relative>,
     start: T,
     dest: &'static mut [u8],
-) -> &'static mut Hook<T> {
+) -> &'static mut Hook<T, Relative> {
     let size = len(end, start) - 24;
-    dest[..size].copy_from_slice(slice::from_raw_parts(util::transmute(start), size));
+    dest[..size].copy_from_slice(slice::from_raw_parts(start.to_addr() as *const u8, size));
 
-    let remote = &mut *(dest[size - mem::size_of_val(end)..].as_mut_ptr() as *mut Hook<T>);
-    remote.set_detour(util::transmute(dest.as_ptr()));
+    let remote =
+        &mut *(dest[size - mem::size_of_val(end)..].as_mut_ptr() as *mut Hook<T, Relative>);
+    remote.set_detour(T::from_addr(dest.as_ptr() as usize));
     remote.set_trampoline((&mut dest[size..]).try_into().unwrap());
     remote
 }
@@ -71,7 +71,8 @@ macro_rules! remote_trampoline_hook {
 
                 #[link_section = "ezhk,rem"]
                 #[allow(non_upper_case_globals)]
-                pub static mut __ez_HOOK: $crate::local::trampoline::Hook<super::__ez_Func> =
+                pub static mut __ez_HOOK:
+                    $crate::local::trampoline::Hook<super::__ez_Func, $crate::Relative> =
                     unsafe { $crate::local::trampoline::Hook::new($name) };
             }
 
@@ -91,7 +92,7 @@ macro_rules! remote_trampoline_hook {
 
             pub unsafe fn copy_to(
                 dest: &'static mut [u8],
-            ) -> &mut $crate::local::trampoline::Hook<__ez_Func> {
+            ) -> &mut $crate::local::trampoline::Hook<__ez_Func, $crate::Relative> {
                 $crate::remote::trampoline::copy_to(&__ez_hook::__ez_HOOK, __ez_hook::$name, dest)
             }
         }
@@ -0,0 +1,345 @@
+use crate::{decode, module::Module, symbol, Error};
+
+use alloc::vec::Vec;
+use core::{ptr, slice};
+
+const MAX_LEN: usize = 128;
+
+#[derive(Clone, Copy)]
+pub struct Pattern {
+    bytes: [u8; MAX_LEN],
+    mask: [bool; MAX_LEN],
+    len: usize,
+    offset: isize,
+    follow: bool,
+}
+
+impl Pattern {
+    pub fn new(signature: &str) -> Result<Self, Error> {
+        let mut pattern = Self {
+            bytes: [0; MAX_LEN],
+            mask: [false; MAX_LEN],
+            len: 0,
+            offset: 0,
+            follow: false,
+        };
+
+        for token in signature.split_ascii_whitespace() {
+            if pattern.len == MAX_LEN {
+                return Err(Error::InvalidPattern);
+            }
+
+            if token.bytes().all(|byte| byte == b'?') && token.len() <= 2 {
+                pattern.mask[pattern.len] = false;
+            } else if token.len() == 2 {
+                pattern.bytes[pattern.len] =
+                    u8::from_str_radix(token, 16).map_err(|_| Error::InvalidPattern)?;
+                pattern.mask[pattern.len] = true;
+            } else {
+                return Err(Error::InvalidPattern);
+            }
+
+            pattern.len += 1;
+        }
+
+        if pattern.len == 0 {
+            return Err(Error::InvalidPattern);
+        }
+
+        Ok(pattern)
+    }
+
+    pub fn offset(mut self, offset: isize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn follow(mut self) -> Self {
+        self.follow = true;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_match(&self, bytes: &[u8]) -> bool {
+        bytes.len() >= self.len && (0..self.len).all(|i| !self.mask[i] || bytes[i] == self.bytes[i])
+    }
+
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        self.find_all(haystack).next()
+    }
+
+    pub fn find_all<'a>(&'a self, haystack: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        let end = (haystack.len() + 1).saturating_sub(self.len);
+        (0..end).filter(move |&i| self.is_match(&haystack[i..]))
+    }
+
+    pub unsafe fn resolve(&self, address: usize) -> usize {
+        let address = address.wrapping_add(self.offset as usize);
+
+        if self.follow {
+            resolve_rel32(address)
+        } else {
+            address
+        }
+    }
+
+    pub unsafe fn scan(&self, module: &Module, mut f: impl FnMut(usize) -> bool) -> bool {
+        for segment in module.executable() {
+            let text = slice::from_raw_parts(segment.address as *const u8, segment.len);
+
+            for i in self.find_all(text) {
+                if f(self.resolve(segment.address + i)) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+pub unsafe fn resolve_rel32(address: usize) -> usize {
+    let code = address as *const u8;
+
+    let operand = match *code {
+        0xE8 | 0xE9 => address + 1,
+        0x0F if *code.add(1) & 0xF0 == 0x80 => address + 2,
+        _ => address,
+    };
+
+    let displacement = ptr::read_unaligned(operand as *const i32);
+    (operand + 4).wrapping_add(displacement as isize as usize)
+}
+
+pub unsafe fn call_sites(module: &Module, target: usize, mut f: impl FnMut(usize) -> bool) -> bool {
+    let mut functions = Vec::new();
+    symbol::symbols(module, |symbol| {
+        if symbol.is_function()
+            && symbol.size != 0
+            && module
+                .executable()
+                .any(|segment| segment.contains(symbol.address))
+        {
+            functions.push((symbol.address, symbol.size));
+        }
+
+        false
+    });
+
+    functions.sort_unstable_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+    let mut end = 0;
+
+    for (address, size) in functions {
+        if address < end {
+            continue;
+        }
+
+        end = address + size;
+
+        let code = slice::from_raw_parts(address as *const u8, size);
+        let mut offset = 0;
+
+        while offset < code.len() {
+            let len = decode::length(&code[offset..]);
+            if len == 0 || offset + len > code.len() {
+                break;
+            }
+
+            let site = address + offset;
+
+            if len == 5
+                && (code[offset] == 0xE8 || code[offset] == 0xE9)
+                && resolve_rel32(site) == target
+                && f(site)
+            {
+                return true;
+            }
+
+            offset += len;
+        }
+    }
+
+    false
+}
+
+pub unsafe fn scan(module: &str, signature: &str) -> Result<usize, Error> {
+    let pattern = Pattern::new(signature)?;
+    let module = Module::find(module).ok_or(Error::ModuleNotFound)?;
+
+    let mut found = None;
+    pattern.scan(&module, |address| {
+        found = Some(address);
+        true
+    });
+
+    found.ok_or(Error::PatternNotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    extern crate std;
+
+    use std::{format, string::String};
+
+    #[inline(never)]
+    fn square(x: i32) -> i32 {
+        util::black_box(x * x)
+    }
+
+    #[test]
+    fn parse() {
+        assert!(Pattern::new("48 8B ?? ? 89").is_ok());
+        assert_eq!(Pattern::new("48 8B ?? ? 89").unwrap().len(), 5);
+        assert_eq!(Pattern::new("").err(), Some(Error::InvalidPattern));
+        assert_eq!(Pattern::new("48 8G").err(), Some(Error::InvalidPattern));
+        assert_eq!(Pattern::new("488B").err(), Some(Error::InvalidPattern));
+    }
+
+    #[test]
+    fn find() {
+        let pattern = Pattern::new("48 8B ?? ?? 89").unwrap();
+
+        let haystack = [
+            0x90, 0x48, 0x8B, 0x05, 0x06, 0x89, 0x48, 0x8B, 0x00, 0x00, 0x89,
+        ];
+        assert_eq!(pattern.find(&haystack), Some(1));
+        assert_eq!(pattern.find_all(&haystack).nth(1), Some(6));
+        assert_eq!(pattern.find(&haystack[..10]), Some(1));
+        assert_eq!(pattern.find(&haystack[2..10]), None);
+    }
+
+    #[test]
+    fn follow() {
+        let call = [0xE8u8, 0x10, 0x00, 0x00, 0x00];
+        assert_eq!(
+            unsafe { resolve_rel32(call.as_ptr() as usize) },
+            call.as_ptr() as usize + 0x15,
+        );
+
+        let jcc = [0x0Fu8, 0x84, 0xFA, 0xFF, 0xFF, 0xFF];
+        assert_eq!(
+            unsafe { resolve_rel32(jcc.as_ptr() as usize) },
+            jcc.as_ptr() as usize,
+        );
+
+        let pattern = Pattern::new("E8 ?? ?? ?? ??").unwrap().follow();
+        let address = call.as_ptr() as usize;
+        assert_eq!(unsafe { pattern.resolve(address) }, address + 0x15);
+
+        let pattern = Pattern::new("90 E8").unwrap().offset(1).follow();
+        assert_eq!(unsafe { pattern.resolve(address - 1) }, address + 0x15);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    core::arch::global_asm!(
+        ".pushsection .text",
+        ".p2align 4",
+        ".type ezhook_scan_callee, @function",
+        "ezhook_scan_callee:",
+        "ret",
+        ".size ezhook_scan_callee, . - ezhook_scan_callee",
+        ".p2align 4",
+        ".type ezhook_scan_first, @function",
+        "ezhook_scan_first:",
+        "call ezhook_scan_callee",
+        "ret",
+        ".size ezhook_scan_first, . - ezhook_scan_first",
+        ".p2align 4",
+        ".type ezhook_scan_inner, @function",
+        "ezhook_scan_inner:",
+        ".byte 0x48, 0xB8, 0xE8",
+        ".long ezhook_scan_callee - . - 4",
+        ".byte 0, 0, 0",
+        "ret",
+        ".size ezhook_scan_inner, . - ezhook_scan_inner",
+        ".p2align 4",
+        ".type ezhook_scan_second, @function",
+        "ezhook_scan_second:",
+        ".byte 0xE9",
+        ".long ezhook_scan_callee - . - 4",
+        ".size ezhook_scan_second, . - ezhook_scan_second",
+        ".popsection",
+        ".pushsection .data.rel.ro, \"aw\"",
+        ".p2align 3",
+        ".globl ezhook_scan_table",
+        ".hidden ezhook_scan_table",
+        "ezhook_scan_table:",
+        ".quad ezhook_scan_callee",
+        ".quad ezhook_scan_first",
+        ".quad ezhook_scan_second",
+        ".popsection",
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    extern "C" {
+        static ezhook_scan_table: [usize; 3];
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn call_sites() {
+        let [callee, first, second] = unsafe { ezhook_scan_table };
+        let module = Module::find("").unwrap();
+
+        let mut sites = std::vec::Vec::new();
+        unsafe {
+            super::call_sites(&module, callee, |site| {
+                sites.push(site);
+                false
+            })
+        };
+        assert_eq!(sites, [first, second]);
+
+        let mut found = None;
+        assert!(unsafe {
+            super::call_sites(&module, callee, |site| {
+                found = Some(site);
+                true
+            })
+        });
+        assert_eq!(found, Some(first));
+    }
+
+    #[test]
+    fn scan_module() {
+        let code = unsafe { slice::from_raw_parts(square as *const u8, 16) };
+        let signature = code
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<std::vec::Vec<String>>()
+            .join(" ");
+
+        let module = Module::find("").unwrap();
+        let pattern = Pattern::new(&signature).unwrap();
+
+        let mut found = false;
+        unsafe {
+            pattern.scan(&module, |address| {
+                found = address == square as usize;
+                found
+            })
+        };
+        assert!(found);
+
+        assert!(unsafe { scan("", &signature) }.is_ok());
+        assert_eq!(
+            unsafe { scan("", "0F 0B 0F 0B 0F 0B 0F 0B 0F 0B CC CC 0F 0B") },
+            Err(Error::PatternNotFound),
+        );
+        assert_eq!(
+            unsafe { scan("ezhook-missing", "90") },
+            Err(Error::ModuleNotFound),
+        );
+    }
+}
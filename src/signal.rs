@@ -0,0 +1,97 @@
+use crate::Error;
+
+use core::{
+    cell::UnsafeCell,
+    hint, mem,
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use libc::{c_int, c_void, sigaction, sigemptyset, siginfo_t, ucontext_t, SA_SIGINFO};
+
+#[cfg(target_arch = "x86")]
+pub(crate) use libc::REG_EIP as REG_IP;
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) use libc::REG_RIP as REG_IP;
+
+#[cfg(feature = "page")]
+pub(crate) use libc::REG_EFL as REG_FLAGS;
+
+pub(crate) type Handler = unsafe extern "C" fn(c_int, *mut siginfo_t, *mut c_void);
+
+pub(crate) struct Chain {
+    lock: AtomicBool,
+    installed: AtomicBool,
+    previous: UnsafeCell<MaybeUninit<sigaction>>,
+}
+
+unsafe impl Sync for Chain {}
+
+impl Chain {
+    pub(crate) const fn new() -> Self {
+        Self {
+            lock: AtomicBool::new(false),
+            installed: AtomicBool::new(false),
+            previous: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    pub(crate) unsafe fn install(&self, signal: c_int, handler: Handler) -> Result<(), Error> {
+        while self
+            .lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+
+        let mut result = Ok(());
+
+        if !self.installed.load(Ordering::Relaxed) {
+            let mut action: sigaction = mem::zeroed();
+            action.sa_sigaction = handler as *const () as usize;
+            action.sa_flags = SA_SIGINFO;
+            sigemptyset(&mut action.sa_mask);
+
+            if sigaction(signal, &action, (*self.previous.get()).as_mut_ptr()) == 0 {
+                self.installed.store(true, Ordering::Release);
+            } else {
+                result = Err(Error::HandlerFailed);
+            }
+        }
+
+        self.lock.store(false, Ordering::Release);
+        result
+    }
+
+    pub(crate) unsafe fn forward(
+        &self,
+        signal: c_int,
+        info: *mut siginfo_t,
+        context: *mut c_void,
+    ) -> bool {
+        let previous = &*(*self.previous.get()).as_ptr();
+
+        if previous.sa_sigaction == libc::SIG_DFL || previous.sa_sigaction == libc::SIG_IGN {
+            sigaction(signal, previous, ptr::null_mut());
+            self.installed.store(false, Ordering::Release);
+            return true;
+        }
+
+        if previous.sa_flags & SA_SIGINFO != 0 {
+            let previous: Handler = mem::transmute(previous.sa_sigaction);
+            previous(signal, info, context);
+        } else {
+            let previous: extern "C" fn(c_int) = mem::transmute(previous.sa_sigaction);
+            previous(signal);
+        }
+
+        false
+    }
+}
+
+pub(crate) unsafe fn registers<'a>(context: *mut c_void) -> &'a mut [libc::greg_t] {
+    &mut (*(context as *mut ucontext_t)).uc_mcontext.gregs
+}
@@ -0,0 +1,666 @@
+use crate::{module::Module, thunk};
+
+use core::{mem, ptr, slice, str};
+
+use libc::{
+    c_char, close, dlclose, dlopen, fstat, mmap, munmap, open, MAP_FAILED, MAP_PRIVATE, O_RDONLY,
+    PROT_READ, RTLD_DEFAULT, RTLD_LAZY, RTLD_NOLOAD,
+};
+
+#[cfg(target_pointer_width = "32")]
+use libc::{Elf32_Ehdr as Ehdr, Elf32_Shdr as Shdr, Elf32_Sym as Sym};
+
+#[cfg(target_pointer_width = "64")]
+use libc::{Elf64_Ehdr as Ehdr, Elf64_Shdr as Shdr, Elf64_Sym as Sym};
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNSYM: u32 = 11;
+const SHN_UNDEF: u16 = 0;
+
+const MAX_IMPLEMENTATIONS: usize = 64;
+
+pub const STT_FUNC: u8 = 2;
+pub const STT_GNU_IFUNC: u8 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    pub name: &'a [u8],
+    pub address: usize,
+    pub size: usize,
+    pub kind: u8,
+}
+
+impl Symbol<'_> {
+    pub fn is_function(&self) -> bool {
+        self.kind == STT_FUNC || self.kind == STT_GNU_IFUNC
+    }
+}
+
+pub struct Image {
+    data: *const u8,
+    len: usize,
+    mapped: bool,
+}
+
+impl Image {
+    pub unsafe fn open(module: &Module) -> Option<Image> {
+        let fd = open(module.path().as_ptr(), O_RDONLY);
+        if fd < 0 {
+            return None;
+        }
+
+        let mut stat = mem::zeroed();
+        let len = if fstat(fd, &mut stat) == 0 {
+            stat.st_size as usize
+        } else {
+            0
+        };
+
+        let data = if len != 0 {
+            mmap(ptr::null_mut(), len, PROT_READ, MAP_PRIVATE, fd, 0)
+        } else {
+            MAP_FAILED
+        };
+
+        close(fd);
+
+        if data == MAP_FAILED {
+            return None;
+        }
+
+        Some(Image {
+            data: data as _,
+            len,
+            mapped: true,
+        })
+    }
+
+    pub unsafe fn from_raw_parts(data: *const u8, len: usize) -> Image {
+        Image {
+            data,
+            len,
+            mapped: false,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data, self.len) }
+    }
+
+    fn read<T: Copy>(&self, offset: usize) -> Option<T> {
+        if offset.checked_add(mem::size_of::<T>())? > self.len {
+            return None;
+        }
+
+        Some(unsafe { ptr::read_unaligned(self.data.add(offset) as *const T) })
+    }
+
+    fn string(&self, offset: usize) -> &[u8] {
+        let bytes = self.as_bytes().get(offset..).unwrap_or_default();
+        let len = bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(bytes.len());
+        &bytes[..len]
+    }
+
+    pub fn symbols(&self, bias: usize, mut f: impl FnMut(&Symbol) -> bool) -> bool {
+        let ehdr: Ehdr = match self.read(0) {
+            Some(ehdr) => ehdr,
+            None => return false,
+        };
+
+        if self.as_bytes()[..4] != *b"\x7FELF" {
+            return false;
+        }
+
+        let shdr = |index: usize| -> Option<Shdr> {
+            self.read(ehdr.e_shoff as usize + index * ehdr.e_shentsize as usize)
+        };
+
+        for index in 0..ehdr.e_shnum as usize {
+            let table = match shdr(index) {
+                Some(table) if table.sh_type == SHT_SYMTAB || table.sh_type == SHT_DYNSYM => table,
+                _ => continue,
+            };
+
+            let strings = match shdr(table.sh_link as usize) {
+                Some(strings) => strings.sh_offset as usize,
+                None => continue,
+            };
+
+            let count = table.sh_size as usize / mem::size_of::<Sym>();
+
+            for index in 0..count {
+                let sym: Sym =
+                    match self.read(table.sh_offset as usize + index * mem::size_of::<Sym>()) {
+                        Some(sym) => sym,
+                        None => break,
+                    };
+
+                if sym.st_shndx == SHN_UNDEF || sym.st_value == 0 {
+                    continue;
+                }
+
+                let symbol = Symbol {
+                    name: self.string(strings + sym.st_name as usize),
+                    address: bias.wrapping_add(sym.st_value as usize),
+                    size: sym.st_size as usize,
+                    kind: sym.st_info & 0xF,
+                };
+
+                if f(&symbol) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        if self.mapped {
+            unsafe { munmap(self.data as _, self.len) };
+        }
+    }
+}
+
+pub unsafe fn symbols(module: &Module, f: impl FnMut(&Symbol) -> bool) -> bool {
+    match Image::open(module) {
+        Some(image) => image.symbols(module.base(), f),
+        None => false,
+    }
+}
+
+pub unsafe fn dlsym(module: &str, name: &str) -> Option<usize> {
+    let mut buffer = [0; 256];
+    let name = name.as_bytes();
+
+    if name.len() >= buffer.len() {
+        return None;
+    }
+
+    buffer[..name.len()].copy_from_slice(name);
+
+    let handle = if module.is_empty() {
+        RTLD_DEFAULT
+    } else {
+        let module = Module::find(module)?;
+
+        let handle = dlopen(module.path().as_ptr(), RTLD_LAZY | RTLD_NOLOAD);
+        if handle.is_null() {
+            return None;
+        }
+
+        handle
+    };
+
+    let address = libc::dlsym(handle, buffer.as_ptr() as *const c_char) as usize;
+
+    if handle != RTLD_DEFAULT {
+        dlclose(handle);
+    }
+
+    if address == 0 {
+        None
+    } else {
+        Some(address)
+    }
+}
+
+pub unsafe fn implementation(symbol: &Symbol) -> usize {
+    if symbol.kind == STT_GNU_IFUNC {
+        let resolver: unsafe extern "C" fn() -> usize = mem::transmute(symbol.address);
+        resolver()
+    } else {
+        symbol.address
+    }
+}
+
+pub unsafe fn implementation_of(address: usize) -> usize {
+    let address = thunk::resolve(address);
+
+    let module = match Module::containing(address) {
+        Some(module) => module,
+        None => return address,
+    };
+
+    let mut found = None;
+    symbols(&module, |symbol| {
+        if symbol.address == address && symbol.kind == STT_GNU_IFUNC {
+            found = Some(implementation(symbol));
+        }
+
+        found.is_some()
+    });
+
+    found.unwrap_or(address)
+}
+
+pub unsafe fn implementations(module: &str, name: &str, mut f: impl FnMut(usize) -> bool) -> bool {
+    let selected = match resolve(module, name) {
+        Some(selected) => selected,
+        None => return false,
+    };
+
+    if f(selected) {
+        return true;
+    }
+
+    let module = match Module::find(module) {
+        Some(module) => module,
+        None => return false,
+    };
+
+    let mut seen = [0; MAX_IMPLEMENTATIONS];
+    let mut count = 0;
+
+    symbols(&module, |symbol| {
+        let suffix = symbol
+            .name
+            .strip_prefix(b"__")
+            .and_then(|rest| rest.strip_prefix(name.as_bytes()))
+            .and_then(|rest| rest.strip_prefix(b"_"));
+
+        match suffix {
+            Some(suffix) if symbol.kind == STT_FUNC && !suffix.starts_with(b"chk") => {}
+            _ => return false,
+        }
+
+        if symbol.address == selected || seen[..count].contains(&symbol.address) {
+            return false;
+        }
+
+        if count < seen.len() {
+            seen[count] = symbol.address;
+            count += 1;
+        }
+
+        f(symbol.address)
+    })
+}
+
+pub unsafe fn lookup(module: &str, name: &str) -> Option<usize> {
+    let module = Module::find(module)?;
+
+    let mut found = None;
+    symbols(&module, |symbol| {
+        if matches(symbol.name, name) {
+            found = Some(implementation(symbol));
+        }
+
+        found.is_some()
+    });
+
+    found
+}
+
+pub unsafe fn resolve(module: &str, name: &str) -> Option<usize> {
+    dlsym(module, name).or_else(|| lookup(module, name))
+}
+
+pub fn matches(symbol: &[u8], name: &str) -> bool {
+    if symbol == name.as_bytes() || parsed(symbol, name) {
+        return true;
+    }
+
+    #[cfg(feature = "demangle")]
+    if demangled(symbol, name) {
+        return true;
+    }
+
+    false
+}
+
+fn parsed(symbol: &[u8], name: &str) -> bool {
+    let (mut rest, nested) = if let Some(rest) = symbol.strip_prefix(b"_ZN") {
+        let qualifiers = rest
+            .iter()
+            .take_while(|byte| matches!(byte, b'r' | b'V' | b'K' | b'R' | b'O'))
+            .count();
+        (&rest[qualifiers..], true)
+    } else if let Some(rest) = symbol.strip_prefix(b"_Z") {
+        (rest, false)
+    } else {
+        return false;
+    };
+
+    let mut expected = name.as_bytes();
+    let mut first = true;
+
+    if nested {
+        if let Some(after) = rest.strip_prefix(b"St") {
+            expected = match expected.strip_prefix(b"std") {
+                Some(expected) => expected,
+                None => return false,
+            };
+            rest = after;
+            first = false;
+        }
+    }
+
+    loop {
+        if rest.first() == Some(&b'E') || !nested && !first {
+            return !first && expected.is_empty();
+        }
+
+        let digits = rest.iter().take_while(|byte| byte.is_ascii_digit()).count();
+        let len = match str::from_utf8(&rest[..digits])
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+        {
+            Some(len) if digits + len <= rest.len() => len,
+            _ => return false,
+        };
+
+        let component = &rest[digits..digits + len];
+        rest = &rest[digits + len..];
+
+        if nested && rest.first() == Some(&b'E') && is_hash(component) {
+            continue;
+        }
+
+        if !first {
+            expected = match expected.strip_prefix(b"::") {
+                Some(expected) => expected,
+                None => return false,
+            };
+        }
+
+        expected = match strip_component(component, expected) {
+            Some(expected) => expected,
+            None => return false,
+        };
+
+        first = false;
+    }
+}
+
+#[cfg(feature = "demangle")]
+fn demangled(symbol: &[u8], name: &str) -> bool {
+    use cpp_demangle::DemangleOptions;
+
+    if symbol.starts_with(b"_R") {
+        return str::from_utf8(symbol)
+            .ok()
+            .and_then(|symbol| rustc_demangle::try_demangle(symbol).ok())
+            .is_some_and(|demangled| alloc::format!("{:#}", demangled) == name);
+    }
+
+    let options = DemangleOptions::new().no_params().no_return_type();
+
+    cpp_demangle::Symbol::new(symbol)
+        .ok()
+        .and_then(|symbol| symbol.demangle(&options).ok())
+        .is_some_and(|demangled| demangled.trim_end_matches(" const") == name)
+}
+
+fn is_hash(component: &[u8]) -> bool {
+    component.len() == 17
+        && component[0] == b'h'
+        && component[1..].iter().all(u8::is_ascii_hexdigit)
+}
+
+fn strip_component<'a>(component: &[u8], mut expected: &'a [u8]) -> Option<&'a [u8]> {
+    const ESCAPES: &[(&[u8], &[u8])] = &[
+        (b"$LT$", b"<"),
+        (b"$GT$", b">"),
+        (b"$u20$", b" "),
+        (b"$RF$", b"&"),
+        (b"$BP$", b"*"),
+        (b"$C$", b","),
+        (b"..", b"::"),
+    ];
+
+    let mut component = if component.starts_with(b"_$") {
+        &component[1..]
+    } else {
+        component
+    };
+
+    while !component.is_empty() {
+        let (escape, raw) = ESCAPES
+            .iter()
+            .find(|(escape, _)| component.starts_with(escape))
+            .copied()
+            .unwrap_or((&component[..1], &component[..1]));
+
+        expected = expected.strip_prefix(raw)?;
+        component = &component[escape.len()..];
+    }
+
+    Some(expected)
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ez_hook_symbol {
+    () => {
+        #[allow(dead_code)]
+        pub unsafe fn hook_symbol(module: &str, name: &str) -> Result<(), $crate::Error> {
+            let target =
+                $crate::symbol::resolve(module, name).ok_or($crate::Error::SymbolNotFound)?;
+            __ez_HOOK.try_hook(<__ez_Func as $crate::FnPtr>::from_addr(target))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{local_swap_hook, util};
+
+    extern crate std;
+
+    #[no_mangle]
+    #[inline(never)]
+    extern "C" fn ezhook_symbol_test(x: i32) -> i32 {
+        util::black_box(x * 3)
+    }
+
+    local_swap_hook! {
+        extern "C" fn plus_one_after(x: i32) -> i32 {
+            orig!(x) + 1
+        }
+    }
+
+    #[inline(never)]
+    fn private_square(x: i32) -> i32 {
+        x * x
+    }
+
+    #[test]
+    fn match_names() {
+        assert!(super::matches(b"strlen", "strlen"));
+        assert!(!super::matches(b"strlen", "strnlen"));
+        assert!(super::matches(b"_ZN2ns3fooEi", "ns::foo"));
+        assert!(!super::matches(b"_ZN2ns3fooEi", "ns::fo"));
+        assert!(!super::matches(b"_ZN2ns3fooEi", "ns"));
+        assert!(super::matches(b"_Z3fooid", "foo"));
+        assert!(super::matches(
+            b"_ZN6ezhook6symbol5tests14private_square17h0123456789abcdefE",
+            "ezhook::symbol::tests::private_square",
+        ));
+        assert!(super::matches(
+            b"_ZN62_$LT$ezhook..local..swap..Hook$LT$T$C$A$GT$$u20$as$u20$Foo$GT$3bar17h0123456789abcdefE",
+            "<ezhook::local::swap::Hook<T,A> as Foo>::bar",
+        ));
+        assert!(super::matches(b"_ZNK2ns1A3getEv", "ns::A::get"));
+        assert!(super::matches(b"_ZNVKR2ns1A3getEv", "ns::A::get"));
+        assert!(!super::matches(b"_ZNK2ns1A3getEv", "ns::A"));
+        assert!(super::matches(b"_ZNSt6thread4joinEv", "std::thread::join"));
+        assert!(!super::matches(b"_ZNSt6thread4joinEv", "thread::join"));
+    }
+
+    #[cfg(feature = "demangle")]
+    #[test]
+    fn match_demangled() {
+        assert!(super::matches(
+            b"_ZN2ns3BoxINS_4ItemEE3getEv",
+            "ns::Box<ns::Item>::get"
+        ));
+        assert!(super::matches(
+            b"_ZNK2ns3BoxINS_4ItemES1_E4pairEv",
+            "ns::Box<ns::Item, ns::Item>::pair"
+        ));
+        assert!(!super::matches(
+            b"_ZN2ns3BoxINS_4ItemEE3getEv",
+            "ns::Box::get"
+        ));
+        assert!(super::matches(
+            b"_RNvNtCs1234_6ezhook6symbol6lookup",
+            "ezhook::symbol::lookup"
+        ));
+        assert!(!super::matches(
+            b"_RNvNtCs1234_6ezhook6symbol6lookup",
+            "ezhook::symbol"
+        ));
+    }
+
+    #[test]
+    fn resolve_symbols() {
+        let getpid = unsafe { dlsym("libc", "getpid") }.unwrap();
+        assert_eq!(unsafe { lookup("libc", "getpid") }, Some(getpid));
+        assert_eq!(unsafe { dlsym("", "getpid") }, Some(getpid));
+
+        assert_eq!(
+            unsafe { resolve("", "ezhook_symbol_test") },
+            Some(ezhook_symbol_test as usize),
+        );
+
+        assert_eq!(unsafe { dlsym("libc", "ezhook_missing") }, None);
+        assert_eq!(unsafe { resolve("libc", "ezhook_missing") }, None);
+    }
+
+    #[test]
+    fn lookup_private() {
+        let private_square = private_square as fn(i32) -> i32;
+        private_square(2);
+
+        let mut found = None;
+        let main = Module::find("").unwrap();
+        unsafe {
+            symbols(&main, |symbol| {
+                if symbol.address == private_square as usize && symbol.name.starts_with(b"_ZN") {
+                    found = Some(symbol.address);
+                }
+
+                found.is_some()
+            })
+        };
+
+        assert_eq!(found, Some(private_square as usize));
+        assert_eq!(
+            unsafe { lookup("", "ezhook::symbol::tests::private_square") },
+            Some(private_square as usize),
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    core::arch::global_asm!(
+        ".pushsection .text",
+        ".p2align 4",
+        ".globl ezhook_ifunc_test",
+        ".hidden ezhook_ifunc_test",
+        ".type ezhook_ifunc_test, @gnu_indirect_function",
+        "ezhook_ifunc_test:",
+        "lea rax, [rip + __ezhook_ifunc_test_fast]",
+        "ret",
+        ".size ezhook_ifunc_test, . - ezhook_ifunc_test",
+        ".p2align 4",
+        ".type __ezhook_ifunc_test_fast, @function",
+        "__ezhook_ifunc_test_fast:",
+        "lea eax, [rdi + 1]",
+        "ret",
+        ".size __ezhook_ifunc_test_fast, . - __ezhook_ifunc_test_fast",
+        ".p2align 4",
+        ".type __ezhook_ifunc_test_slow, @function",
+        "__ezhook_ifunc_test_slow:",
+        "mov eax, edi",
+        "inc eax",
+        "ret",
+        ".size __ezhook_ifunc_test_slow, . - __ezhook_ifunc_test_slow",
+        ".popsection",
+        ".pushsection .data.rel.ro, \"aw\"",
+        ".p2align 3",
+        ".globl ezhook_ifunc_table",
+        ".hidden ezhook_ifunc_table",
+        "ezhook_ifunc_table:",
+        ".quad __ezhook_ifunc_test_fast",
+        ".quad __ezhook_ifunc_test_slow",
+        ".popsection",
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    extern "C" {
+        static ezhook_ifunc_table: [usize; 2];
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn ifunc() {
+        let [fast, slow] = unsafe { ezhook_ifunc_table };
+        let main = Module::find("").unwrap();
+
+        let mut resolver = None;
+        unsafe {
+            symbols(&main, |symbol| {
+                if symbol.name == b"ezhook_ifunc_test" {
+                    assert_eq!(symbol.kind, STT_GNU_IFUNC);
+                    resolver = Some(symbol.address);
+                }
+
+                resolver.is_some()
+            })
+        };
+
+        let resolver = resolver.unwrap();
+
+        assert_ne!(resolver, fast);
+        assert_eq!(unsafe { lookup("", "ezhook_ifunc_test") }, Some(fast));
+        assert_eq!(unsafe { implementation_of(resolver) }, fast);
+        assert_eq!(unsafe { implementation_of(fast) }, fast);
+
+        let mut found = std::vec::Vec::new();
+        unsafe {
+            implementations("", "ezhook_ifunc_test", |address| {
+                found.push(address);
+                false
+            })
+        };
+
+        assert_eq!(found, [fast, slow]);
+
+        let memcpy = unsafe { dlsym("libc", "memcpy") }.unwrap();
+        let mut first = None;
+        unsafe {
+            implementations("libc", "memcpy", |address| {
+                first = Some(address);
+                true
+            })
+        };
+        assert_eq!(first, Some(memcpy));
+    }
+
+    #[test]
+    fn hook_symbol() {
+        util::unprotect(ezhook_symbol_test as _, 5);
+
+        assert_eq!(
+            unsafe { plus_one_after::hook_symbol("", "ezhook_missing") },
+            Err(crate::Error::SymbolNotFound),
+        );
+
+        unsafe { plus_one_after::hook_symbol("", "ezhook_symbol_test") }.unwrap();
+        unsafe { plus_one_after::enable() };
+
+        assert_eq!(ezhook_symbol_test(2), 7);
+
+        unsafe { plus_one_after::disable() };
+        unsafe { plus_one_after::unhook() };
+
+        assert_eq!(ezhook_symbol_test(2), 6);
+    }
+}
@@ -0,0 +1,515 @@
+use crate::{
+    info::{ENABLED, INSTALLED},
+    signal::{self, Chain, REG_IP},
+    Error, HookInfo,
+};
+
+#[cfg(feature = "registry")]
+use crate::registry;
+
+use core::{
+    arch::global_asm,
+    mem,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use std::cell::Cell;
+
+use libc::{
+    c_int, c_long, c_void, prctl, siginfo_t, SYS_clone, SYS_clone3, SYS_rt_sigreturn, SYS_vfork,
+    REG_R10, REG_R8, REG_R9, REG_RAX, REG_RDI, REG_RDX, REG_RSI, REG_RSP, SIGSYS,
+};
+
+const MAX_SYSCALLS: usize = 512;
+
+const PR_SET_SYSCALL_USER_DISPATCH: c_int = 59;
+const PR_SYS_DISPATCH_OFF: usize = 0;
+const PR_SYS_DISPATCH_ON: usize = 1;
+const SYS_USER_DISPATCH: c_int = 2;
+
+const ALLOW: u8 = 0;
+const BLOCK: u8 = 1;
+
+pub struct Syscall {
+    pub number: usize,
+    pub args: [usize; 6],
+}
+
+impl Syscall {
+    pub unsafe fn invoke(&self) -> isize {
+        syscall(self.number, self.args)
+    }
+}
+
+pub type Detour = fn(&Syscall) -> isize;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicUsize = AtomicUsize::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE: AtomicBool = AtomicBool::new(false);
+
+static DETOURS: [AtomicUsize; MAX_SYSCALLS] = [EMPTY; MAX_SYSCALLS];
+static CLAIMED: [AtomicBool; MAX_SYSCALLS] = [FREE; MAX_SYSCALLS];
+
+static CHAIN: Chain = Chain::new();
+
+std::thread_local! {
+    static SELECTOR: Cell<u8> = const { Cell::new(ALLOW) };
+    static DISCARD: Cell<u8> = const { Cell::new(ALLOW) };
+}
+
+#[repr(C)]
+struct Resume {
+    selector: *mut u8,
+    stack: usize,
+}
+
+global_asm!(
+    ".pushsection .text",
+    ".p2align 4",
+    ".globl __ezhook_syscall_start",
+    ".hidden __ezhook_syscall_start",
+    "__ezhook_syscall_start:",
+    ".globl __ezhook_syscall",
+    ".hidden __ezhook_syscall",
+    "__ezhook_syscall:",
+    "mov rax, rdi",
+    "mov rdi, [rsi]",
+    "mov rdx, [rsi + 16]",
+    "mov r10, [rsi + 24]",
+    "mov r8, [rsi + 32]",
+    "mov r9, [rsi + 40]",
+    "mov rsi, [rsi + 8]",
+    "syscall",
+    "ret",
+    ".globl __ezhook_syscall_handler",
+    ".hidden __ezhook_syscall_handler",
+    "__ezhook_syscall_handler:",
+    "sub rsp, 8",
+    "call {dispatch}",
+    "add rsp, 16",
+    "mov byte ptr [rax], {block}",
+    "test rdx, rdx",
+    "jz 2f",
+    "mov rsp, rdx",
+    "2:",
+    "mov eax, 15",
+    "syscall",
+    "ud2",
+    ".globl __ezhook_syscall_end",
+    ".hidden __ezhook_syscall_end",
+    "__ezhook_syscall_end:",
+    ".popsection",
+    dispatch = sym dispatch,
+    block = const BLOCK,
+);
+
+extern "C" {
+    fn __ezhook_syscall_start();
+    fn __ezhook_syscall(number: usize, args: *const [usize; 6]) -> isize;
+    fn __ezhook_syscall_handler(signal: c_int, info: *mut siginfo_t, context: *mut c_void);
+    fn __ezhook_syscall_end();
+}
+
+pub unsafe fn syscall(number: usize, args: [usize; 6]) -> isize {
+    __ezhook_syscall(number, &args)
+}
+
+unsafe extern "C" fn dispatch(signal: c_int, info: *mut siginfo_t, context: *mut c_void) -> Resume {
+    let selector = SELECTOR.with(Cell::as_ptr);
+    *selector = ALLOW;
+
+    let registers = signal::registers(context);
+
+    if (*info).si_code == SYS_USER_DISPATCH {
+        let number = registers[REG_RAX as usize] as c_long;
+
+        if number == SYS_rt_sigreturn {
+            return Resume {
+                selector,
+                stack: registers[REG_RSP as usize] as usize,
+            };
+        }
+
+        if [SYS_clone, SYS_clone3, SYS_vfork].contains(&number) {
+            registers[REG_IP as usize] -= 2;
+
+            return Resume {
+                selector: DISCARD.with(Cell::as_ptr),
+                stack: 0,
+            };
+        }
+
+        let call = Syscall {
+            number: registers[REG_RAX as usize] as usize,
+            args: [REG_RDI, REG_RSI, REG_RDX, REG_R10, REG_R8, REG_R9]
+                .map(|register| registers[register as usize] as usize),
+        };
+
+        let detour = DETOURS
+            .get(call.number)
+            .map_or(0, |detour| detour.load(Ordering::Acquire));
+
+        registers[REG_RAX as usize] = if detour == 0 {
+            call.invoke()
+        } else {
+            mem::transmute::<usize, Detour>(detour)(&call)
+        } as _;
+    } else if CHAIN.forward(signal, info, context) {
+        registers[REG_IP as usize] -= 2;
+    }
+
+    Resume { selector, stack: 0 }
+}
+
+pub unsafe fn try_attach() -> Result<(), Error> {
+    CHAIN.install(SIGSYS, __ezhook_syscall_handler)?;
+
+    let start = __ezhook_syscall_start as *const () as usize;
+    let end = __ezhook_syscall_end as *const () as usize;
+    let selector = SELECTOR.with(Cell::as_ptr);
+
+    *selector = ALLOW;
+
+    if prctl(
+        PR_SET_SYSCALL_USER_DISPATCH,
+        PR_SYS_DISPATCH_ON,
+        start,
+        end - start,
+        selector,
+    ) != 0
+    {
+        return Err(Error::DispatchFailed);
+    }
+
+    *selector = BLOCK;
+
+    Ok(())
+}
+
+pub unsafe fn attach() {
+    try_attach().unwrap()
+}
+
+pub unsafe fn try_detach() -> Result<(), Error> {
+    SELECTOR.with(|selector| selector.set(ALLOW));
+
+    if prctl(PR_SET_SYSCALL_USER_DISPATCH, PR_SYS_DISPATCH_OFF, 0, 0, 0) != 0 {
+        return Err(Error::DispatchFailed);
+    }
+
+    Ok(())
+}
+
+pub unsafe fn detach() {
+    try_detach().unwrap()
+}
+
+#[cfg(feature = "registry")]
+unsafe fn restore(info: &HookInfo, patched: bool) -> Result<(), Error> {
+    let detour = if patched { info.detour } else { 0 };
+    (*(info.target as *const AtomicUsize)).store(detour, Ordering::Release);
+
+    Ok(())
+}
+
+pub struct Hook {
+    detour: Detour,
+    number: usize,
+    state: u8,
+    #[cfg(feature = "registry")]
+    id: usize,
+}
+
+impl Hook {
+    pub const unsafe fn new(detour: Detour) -> Self {
+        Self {
+            detour,
+            number: 0,
+            state: 0,
+            #[cfg(feature = "registry")]
+            id: 0,
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.state & INSTALLED != 0
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state & ENABLED != 0
+    }
+
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    pub unsafe fn try_set_detour(&mut self, detour: Detour) -> Result<(), Error> {
+        self.guarded(|hook, killed| {
+            if hook.is_enabled() && !killed {
+                DETOURS[hook.number].store(detour as usize, Ordering::Release);
+            }
+
+            hook.detour = detour;
+
+            Ok(())
+        })
+    }
+
+    pub unsafe fn set_detour(&mut self, detour: Detour) {
+        self.try_set_detour(detour).unwrap()
+    }
+
+    pub unsafe fn try_hook(&mut self, number: usize) -> Result<(), Error> {
+        #[cfg(feature = "registry")]
+        registry::check()?;
+
+        if self.is_installed() {
+            return Err(Error::AlreadyInstalled);
+        }
+
+        let claimed = CLAIMED.get(number).ok_or(Error::OutOfRange)?;
+
+        CHAIN.install(SIGSYS, __ezhook_syscall_handler)?;
+
+        if claimed
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(Error::AlreadyInstalled);
+        }
+
+        self.number = number;
+        self.state = INSTALLED;
+
+        #[cfg(feature = "registry")]
+        match registry::join(self.info().unwrap(), false, restore) {
+            Ok(id) => self.id = id,
+            Err(error) => {
+                self.try_unhook()?;
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn hook(&mut self, number: usize) {
+        self.try_hook(number).unwrap()
+    }
+
+    pub unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        CLAIMED[self.number].store(false, Ordering::Release);
+        self.state = 0;
+
+        #[cfg(feature = "registry")]
+        registry::leave(self.id);
+
+        Ok(())
+    }
+
+    pub unsafe fn unhook(&mut self) {
+        self.try_unhook().unwrap()
+    }
+
+    pub unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        if !self.is_installed() {
+            return Err(Error::NotInstalled);
+        }
+
+        self.guarded(|hook, killed| {
+            if killed && !hook.is_enabled() {
+                return Err(Error::Killed);
+            }
+
+            let detour = if hook.is_enabled() {
+                0
+            } else {
+                hook.detour as usize
+            };
+
+            DETOURS[hook.number].store(detour, Ordering::Release);
+            hook.state ^= ENABLED;
+
+            Ok(())
+        })
+    }
+
+    pub unsafe fn toggle(&mut self) {
+        self.try_toggle().unwrap()
+    }
+
+    pub unsafe fn try_enable(&mut self) -> Result<(), Error> {
+        if self.is_enabled() {
+            return Err(Error::Enabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn enable(&mut self) {
+        self.try_enable().unwrap()
+    }
+
+    pub unsafe fn try_disable(&mut self) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Err(Error::Disabled);
+        }
+
+        self.try_toggle()
+    }
+
+    pub unsafe fn disable(&mut self) {
+        self.try_disable().unwrap()
+    }
+
+    pub fn info(&self) -> Option<HookInfo> {
+        if !self.is_installed() {
+            return None;
+        }
+
+        Some(HookInfo {
+            target: &DETOURS[self.number] as *const _ as usize,
+            detour: self.detour as usize,
+            trampoline: 0,
+            trampoline_len: 0,
+            original: [0; 5],
+            patched: [0; 5],
+            stolen: 0,
+        })
+    }
+
+    #[cfg(feature = "registry")]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        registry::guard(|guard| {
+            let result = f(self, guard.killed);
+
+            if let Some(info) = self.info() {
+                guard.update(self.id, info, self.is_enabled());
+            }
+
+            result
+        })
+    }
+
+    #[cfg(not(feature = "registry"))]
+    unsafe fn guarded(
+        &mut self,
+        f: impl FnOnce(&mut Self, bool) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        f(self, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libc::{
+        getpid, getppid, pthread_kill, pthread_self, sighandler_t, signal, SYS_getppid, SIGUSR1,
+    };
+    use std::sync::mpsc;
+
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn received(_: c_int) {
+        RECEIVED.store(true, Ordering::SeqCst);
+    }
+
+    fn fake(_: &Syscall) -> isize {
+        4242
+    }
+
+    fn shifted(call: &Syscall) -> isize {
+        unsafe { call.invoke() + 1 }
+    }
+
+    #[test]
+    fn hook() {
+        let parent = unsafe { getppid() };
+        let pid = unsafe { getpid() };
+
+        assert_eq!(
+            unsafe { syscall(SYS_getppid as usize, [0; 6]) },
+            parent as isize
+        );
+
+        let mut hook = unsafe { Hook::new(fake) };
+
+        assert_eq!(
+            unsafe { hook.try_hook(MAX_SYSCALLS) },
+            Err(Error::OutOfRange)
+        );
+
+        std::thread::spawn(move || unsafe {
+            attach();
+
+            hook.hook(SYS_getppid as usize);
+            assert_eq!(getppid(), parent);
+
+            hook.enable();
+            assert_eq!(getppid(), 4242);
+            assert_eq!(getpid(), pid);
+
+            hook.set_detour(shifted);
+            assert_eq!(getppid(), parent + 1);
+
+            hook.disable();
+            assert_eq!(getppid(), parent);
+
+            hook.unhook();
+            detach();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn async_signal() {
+        if !crate::util::isolated("syscall::tests::async_signal") {
+            return;
+        }
+
+        let parent = unsafe { getppid() };
+        let (sender, receiver) = mpsc::channel();
+
+        unsafe { signal(SIGUSR1, received as *const () as sighandler_t) };
+
+        let worker = std::thread::spawn(move || unsafe {
+            let mut hook = Hook::new(fake);
+
+            attach();
+            hook.hook(SYS_getppid as usize);
+            hook.enable();
+
+            sender.send(pthread_self()).unwrap();
+
+            while !RECEIVED.load(Ordering::SeqCst) {
+                core::hint::spin_loop();
+            }
+
+            assert_eq!(getppid(), 4242);
+
+            hook.disable();
+            assert_eq!(getppid(), parent);
+
+            hook.unhook();
+            detach();
+        });
+
+        let thread = receiver.recv().unwrap();
+        assert_eq!(unsafe { pthread_kill(thread, SIGUSR1) }, 0);
+
+        worker.join().unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+#[cfg(all(feature = "module", target_os = "linux"))]
+use crate::module::Module;
+
+use core::ptr;
+
+const MAX_DEPTH: usize = 16;
+
+unsafe fn read_i32(address: usize) -> isize {
+    ptr::read_unaligned(address as *const i32) as isize
+}
+
+unsafe fn lazy(address: usize) -> bool {
+    let mut code = address as *const u8;
+
+    if let [0xF3, 0x0F, 0x1E, 0xFA | 0xFB] = *(code as *const [u8; 4]) {
+        code = code.add(4);
+    }
+
+    *code == 0x68
+        && match *code.add(5) {
+            0xE9 => true,
+            0xF2 => *code.add(6) == 0xE9,
+            _ => false,
+        }
+}
+
+#[cfg(all(feature = "module", target_os = "linux"))]
+unsafe fn bind(slot: usize) -> Option<usize> {
+    let module = Module::containing(slot)?;
+    let name = module.import(slot)?;
+    let address = libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr());
+
+    if address.is_null() {
+        None
+    } else {
+        Some(address as usize)
+    }
+}
+
+#[cfg(not(all(feature = "module", target_os = "linux")))]
+unsafe fn bind(_: usize) -> Option<usize> {
+    None
+}
+
+pub unsafe fn next(address: usize) -> Option<usize> {
+    let code = address as *const u8;
+    let mut offset = 0;
+
+    if let [0xF3, 0x0F, 0x1E, 0xFA | 0xFB] = *(code as *const [u8; 4]) {
+        offset += 4;
+    }
+
+    if *code.add(offset) == 0xF2 {
+        offset += 1;
+    }
+
+    let address = address + offset;
+    let code = address as *const u8;
+
+    match *code {
+        0xE9 => Some((address + 5).wrapping_add(read_i32(address + 1) as usize)),
+        0xEB => Some((address + 2).wrapping_add(*code.add(1) as i8 as usize)),
+        0xFF if *code.add(1) == 0x25 => {
+            #[cfg(target_arch = "x86_64")]
+            let slot = (address + 6).wrapping_add(read_i32(address + 2) as usize);
+
+            #[cfg(target_arch = "x86")]
+            let slot = read_i32(address + 2) as usize;
+
+            let destination = ptr::read_unaligned(slot as *const usize);
+
+            if lazy(destination) {
+                return bind(slot);
+            }
+
+            Some(destination)
+        }
+        _ => None,
+    }
+}
+
+pub unsafe fn resolve(mut address: usize) -> usize {
+    for _ in 0..MAX_DEPTH {
+        match next(address) {
+            Some(destination) => address = destination,
+            None => break,
+        }
+    }
+
+    address
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        let mut code = [0u8; 32];
+        let base = code.as_ptr() as usize;
+
+        code[0..2].copy_from_slice(&[0xEB, 0x06]);
+        code[8..12].copy_from_slice(&[0xF3, 0x0F, 0x1E, 0xFA]);
+        code[12..17].copy_from_slice(&[0xE9, 0x03, 0x00, 0x00, 0x00]);
+        code[20] = 0x90;
+
+        assert_eq!(unsafe { next(base) }, Some(base + 8));
+        assert_eq!(unsafe { next(base + 8) }, Some(base + 20));
+        assert_eq!(unsafe { next(base + 20) }, None);
+        assert_eq!(unsafe { super::resolve(base) }, base + 20);
+
+        let slot = base + 20;
+        code[0..2].copy_from_slice(&[0xFF, 0x25]);
+
+        #[cfg(target_arch = "x86_64")]
+        code[2..6].copy_from_slice(&(slot as isize - base as isize - 6).to_ne_bytes()[..4]);
+
+        #[cfg(target_arch = "x86")]
+        code[2..6].copy_from_slice(&slot.to_ne_bytes());
+
+        code[20..20 + core::mem::size_of::<usize>()].copy_from_slice(&(base + 8).to_ne_bytes());
+
+        assert_eq!(unsafe { next(base) }, Some(base + 8));
+
+        code[8..18].copy_from_slice(&[0x68, 0x01, 0x00, 0x00, 0x00, 0xF2, 0xE9, 0, 0, 0]);
+
+        assert!(unsafe { lazy(base + 8) });
+        assert_eq!(unsafe { next(base) }, None);
+        assert_eq!(unsafe { super::resolve(base) }, base);
+    }
+}
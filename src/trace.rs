@@ -0,0 +1,469 @@
+use crate::{
+    decode,
+    module::Module,
+    probe::Probe,
+    symbol::{self, Symbol, STT_FUNC},
+    transaction::Transaction,
+    Error,
+};
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use std::{boxed::Box, cell::Cell, sync::Arc, vec::Vec};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    pub address: usize,
+    pub depth: usize,
+    pub exit: bool,
+}
+
+struct Slot {
+    sequence: AtomicUsize,
+    event: UnsafeCell<Event>,
+}
+
+pub struct Ring {
+    slots: Box<[Slot]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+
+        Self {
+            slots: (0..capacity)
+                .map(|index| Slot {
+                    sequence: AtomicUsize::new(index),
+                    event: UnsafeCell::new(Event::default()),
+                })
+                .collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn push(&self, event: Event) -> bool {
+        let mask = self.slots.len() - 1;
+        let mut position = self.head.load(Ordering::Relaxed);
+
+        loop {
+            let slot = &self.slots[position & mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            match sequence as isize - position as isize {
+                0 => match self.head.compare_exchange_weak(
+                    position,
+                    position + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { *slot.event.get() = event };
+                        slot.sequence.store(position + 1, Ordering::Release);
+                        return true;
+                    }
+                    Err(current) => position = current,
+                },
+                difference if difference < 0 => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                _ => position = self.head.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<Event> {
+        let mask = self.slots.len() - 1;
+        let mut position = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            let slot = &self.slots[position & mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            match sequence as isize - (position + 1) as isize {
+                0 => match self.tail.compare_exchange_weak(
+                    position,
+                    position + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let event = unsafe { *slot.event.get() };
+                        slot.sequence.store(position + mask + 1, Ordering::Release);
+                        return Some(event);
+                    }
+                    Err(current) => position = current,
+                },
+                difference if difference < 0 => return None,
+                _ => position = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+}
+
+pub fn relocatable(code: &[u8]) -> Option<usize> {
+    let mut len = 0;
+
+    while len < 5 {
+        let instruction = &code.get(len..)?;
+        let size = decode::length(instruction);
+        if size == 0
+            || len + size > code.len()
+            || !decode::position_independent(&instruction[..size])
+        {
+            return None;
+        }
+
+        len += size;
+    }
+
+    Some(len)
+}
+
+std::thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+struct Traced {
+    address: usize,
+    count: Arc<AtomicUsize>,
+    probe: Probe,
+}
+
+pub struct Trace {
+    traced: Vec<Traced>,
+    ring: Arc<Ring>,
+}
+
+impl Trace {
+    pub unsafe fn module(
+        name: &str,
+        capacity: usize,
+        mut filter: impl FnMut(&Symbol) -> bool,
+    ) -> Result<Self, Error> {
+        let module = Module::find(name).ok_or(Error::ModuleNotFound)?;
+
+        let mut targets = Vec::new();
+        symbol::symbols(&module, |symbol| {
+            if symbol.kind == STT_FUNC
+                && symbol.size >= 5
+                && module
+                    .executable()
+                    .any(|segment| segment.contains(symbol.address))
+                && !targets.contains(&symbol.address)
+                && filter(symbol)
+            {
+                let code = core::slice::from_raw_parts(symbol.address as *const u8, symbol.size);
+                if relocatable(code).is_some() {
+                    targets.push(symbol.address);
+                }
+            }
+
+            false
+        });
+
+        let ring = Arc::new(Ring::new(capacity));
+        let mut trace = Self {
+            traced: Vec::with_capacity(targets.len()),
+            ring,
+        };
+
+        for address in targets {
+            let count = Arc::new(AtomicUsize::new(0));
+
+            let entry = {
+                let ring = trace.ring.clone();
+                let count = count.clone();
+
+                move |_: &_| {
+                    count.fetch_add(1, Ordering::Relaxed);
+
+                    let depth = DEPTH.with(|depth| depth.replace(depth.get() + 1));
+                    ring.push(Event {
+                        address,
+                        depth,
+                        exit: false,
+                    });
+
+                    depth
+                }
+            };
+
+            let exit = {
+                let ring = trace.ring.clone();
+
+                move |_: &_, depth| {
+                    DEPTH.with(|cell| cell.set(depth));
+                    ring.push(Event {
+                        address,
+                        depth,
+                        exit: true,
+                    });
+                }
+            };
+
+            match Probe::new(address, entry, exit) {
+                Ok(probe) => trace.traced.push(Traced {
+                    address,
+                    count,
+                    probe,
+                }),
+                Err(Error::OutOfRange) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        trace.try_enable()?;
+
+        Ok(trace)
+    }
+
+    pub fn len(&self) -> usize {
+        self.traced.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.traced.is_empty()
+    }
+
+    pub fn ring(&self) -> &Ring {
+        &self.ring
+    }
+
+    pub fn counts(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.traced
+            .iter()
+            .map(|traced| (traced.address, traced.count.load(Ordering::Relaxed)))
+    }
+
+    pub fn count(&self, address: usize) -> Option<usize> {
+        self.counts()
+            .find(|&(traced, _)| traced == address)
+            .map(|(_, count)| count)
+    }
+
+    pub unsafe fn try_enable(&mut self) -> Result<(), Error> {
+        let mut transaction = Transaction::begin();
+
+        for traced in &mut self.traced {
+            if !traced.probe.is_enabled() {
+                let handle = transaction.add(traced.probe.hook_mut());
+                transaction.enable(handle);
+            }
+        }
+
+        transaction.commit()
+    }
+
+    pub unsafe fn enable(&mut self) {
+        self.try_enable().unwrap()
+    }
+
+    pub unsafe fn try_disable(&mut self) -> Result<(), Error> {
+        let mut transaction = Transaction::begin();
+
+        for traced in &mut self.traced {
+            if traced.probe.is_enabled() {
+                let handle = transaction.add(traced.probe.hook_mut());
+                transaction.disable(handle);
+            }
+        }
+
+        transaction.commit()
+    }
+
+    pub unsafe fn disable(&mut self) {
+        self.try_disable().unwrap()
+    }
+
+    pub unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        self.try_disable()?;
+
+        for mut traced in self.traced.drain(..) {
+            traced.probe.try_unhook()?;
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn unhook(&mut self) {
+        self.try_unhook().unwrap()
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        let _ = unsafe { self.try_unhook() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring() {
+        let ring = Ring::new(3);
+        assert_eq!(ring.capacity(), 4);
+
+        for address in 0..5 {
+            ring.push(Event {
+                address,
+                depth: 0,
+                exit: false,
+            });
+        }
+
+        assert_eq!(ring.dropped(), 1);
+        assert_eq!(ring.pop().map(|event| event.address), Some(0));
+        assert!(ring.push(Event::default()));
+        assert_eq!(
+            (0..5)
+                .map(|_| ring.pop().map(|event| event.address))
+                .collect::<Vec<_>>(),
+            [Some(1), Some(2), Some(3), Some(0), None],
+        );
+    }
+
+    #[test]
+    fn prologue() {
+        assert_eq!(relocatable(&[0x55, 0x48, 0x89, 0xE5, 0x41, 0x57]), Some(6));
+        assert_eq!(relocatable(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3]), Some(5));
+        assert_eq!(relocatable(&[0x31, 0xC0, 0xC3, 0x90, 0x90]), None);
+        assert_eq!(relocatable(&[0x55, 0xE8, 0x00, 0x00, 0x00, 0x00]), None);
+        assert_eq!(relocatable(&[0x55, 0x90]), None);
+
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            relocatable(&[0x48, 0x8B, 0x05, 0x00, 0x00, 0x00, 0x00]),
+            None,
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    core::arch::global_asm!(
+        ".pushsection .text",
+        ".p2align 4",
+        ".type ezhook_trace_inner, @function",
+        "ezhook_trace_inner:",
+        "push rbx",
+        "mov rbx, rdi",
+        "lea rax, [rbx + 1]",
+        "pop rbx",
+        "ret",
+        ".size ezhook_trace_inner, . - ezhook_trace_inner",
+        ".p2align 4",
+        ".type ezhook_trace_outer, @function",
+        "ezhook_trace_outer:",
+        "push rbx",
+        "push r12",
+        "mov rbx, rdi",
+        "sub rsp, 8",
+        "call ezhook_trace_inner",
+        "mov r12, rax",
+        "mov rdi, rbx",
+        "call ezhook_trace_inner",
+        "add rax, r12",
+        "add rsp, 8",
+        "pop r12",
+        "pop rbx",
+        "ret",
+        ".size ezhook_trace_outer, . - ezhook_trace_outer",
+        ".p2align 4",
+        ".type ezhook_trace_short, @function",
+        "ezhook_trace_short:",
+        "xor eax, eax",
+        "ret",
+        "nop",
+        "nop",
+        "nop",
+        ".size ezhook_trace_short, . - ezhook_trace_short",
+        ".popsection",
+        ".pushsection .data.rel.ro, \"aw\"",
+        ".p2align 3",
+        ".globl ezhook_trace_table",
+        ".hidden ezhook_trace_table",
+        "ezhook_trace_table:",
+        ".quad ezhook_trace_inner",
+        ".quad ezhook_trace_outer",
+        ".quad ezhook_trace_short",
+        ".popsection",
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    extern "C" {
+        static ezhook_trace_table: [extern "C" fn(usize) -> usize; 3];
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn module() {
+        let [inner, outer, short] = unsafe { ezhook_trace_table };
+
+        assert_eq!(outer(1), 4);
+
+        let mut trace =
+            unsafe { Trace::module("", 64, |symbol| symbol.name.starts_with(b"ezhook_trace_")) }
+                .unwrap();
+
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace.count(short as usize), None);
+
+        assert_eq!(outer(1), 4);
+        assert_eq!(short(1), 0);
+
+        assert_eq!(trace.count(outer as usize), Some(1));
+        assert_eq!(trace.count(inner as usize), Some(2));
+
+        let events = core::iter::from_fn(|| trace.ring().pop())
+            .map(|event| (event.address, event.depth, event.exit))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            events,
+            [
+                (outer as usize, 0, false),
+                (inner as usize, 1, false),
+                (inner as usize, 1, true),
+                (inner as usize, 1, false),
+                (inner as usize, 1, true),
+                (outer as usize, 0, true),
+            ],
+        );
+
+        unsafe { trace.unhook() };
+
+        assert!(trace.is_empty());
+        assert_eq!(outer(1), 4);
+        assert!(trace.ring().pop().is_none());
+
+        let trace =
+            unsafe { Trace::module("", 64, |symbol| symbol.name.starts_with(b"ezhook_trace_")) }
+                .unwrap();
+
+        assert_eq!(trace.len(), 2);
+        drop(trace);
+
+        assert_eq!(unsafe { *(inner as *const u8) }, 0x53);
+        assert_eq!(unsafe { *(outer as *const u8) }, 0x53);
+        assert_eq!(outer(1), 4);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::{
+    freeze,
+    patch::DynPatch,
+    protect::{self, Unprotected},
+    Error, FnPtr, HookInfo, Patch,
+};
+
+use alloc::vec::Vec;
+use core::{marker::PhantomData, mem, ops::Range};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Hook(usize),
+    Unhook,
+    Toggle,
+    Enable,
+    Disable,
+}
+
+pub struct Handle<T> {
+    index: usize,
+    target: PhantomData<T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+#[derive(Clone, Copy)]
+struct State {
+    installed: bool,
+    enabled: bool,
+    target: usize,
+    info: Option<HookInfo>,
+}
+
+#[derive(Default)]
+pub struct Transaction<'a> {
+    hooks: Vec<&'a mut dyn DynPatch>,
+    ops: Vec<(usize, Op)>,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn begin() -> Self {
+        Self::default()
+    }
+
+    pub fn add<P: Patch>(&mut self, hook: &'a mut P) -> Handle<P::Target> {
+        self.hooks.push(hook);
+
+        Handle {
+            index: self.hooks.len() - 1,
+            target: PhantomData,
+        }
+    }
+
+    pub fn hook<T: FnPtr>(&mut self, handle: Handle<T>, target: T) -> &mut Self {
+        self.ops.push((handle.index, Op::Hook(target.to_addr())));
+        self
+    }
+
+    pub fn unhook<T>(&mut self, handle: Handle<T>) -> &mut Self {
+        self.ops.push((handle.index, Op::Unhook));
+        self
+    }
+
+    pub fn toggle<T>(&mut self, handle: Handle<T>) -> &mut Self {
+        self.ops.push((handle.index, Op::Toggle));
+        self
+    }
+
+    pub fn enable<T>(&mut self, handle: Handle<T>) -> &mut Self {
+        self.ops.push((handle.index, Op::Enable));
+        self
+    }
+
+    pub fn disable<T>(&mut self, handle: Handle<T>) -> &mut Self {
+        self.ops.push((handle.index, Op::Disable));
+        self
+    }
+
+    unsafe fn validate(&self) -> Result<(Vec<usize>, Vec<Range<usize>>), Error> {
+        let mut states = self
+            .hooks
+            .iter()
+            .map(|hook| State {
+                installed: hook.is_installed(),
+                enabled: hook.is_enabled(),
+                target: hook.info().map_or(0, |info| info.target),
+                info: hook.info(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut sites = Vec::new();
+        let mut busy = Vec::new();
+
+        for &(index, op) in &self.ops {
+            let state = &mut states[index];
+
+            match op {
+                Op::Hook(_) if state.installed => return Err(Error::AlreadyInstalled),
+                Op::Unhook | Op::Toggle | Op::Enable | Op::Disable if !state.installed => {
+                    return Err(Error::NotInstalled)
+                }
+                Op::Unhook | Op::Enable if state.enabled => return Err(Error::Enabled),
+                Op::Disable if !state.enabled => return Err(Error::Disabled),
+                _ => {}
+            }
+
+            match op {
+                Op::Hook(target) => {
+                    state.installed = true;
+                    state.target = target;
+                    state.info = None;
+                }
+                Op::Unhook => state.installed = false,
+                Op::Toggle | Op::Enable | Op::Disable => state.enabled = !state.enabled,
+            }
+
+            if let Op::Unhook | Op::Toggle | Op::Enable | Op::Disable = op {
+                sites.push(state.target);
+
+                let info = state.info.filter(|info| info.target == state.target);
+                let prologue = info.map_or(5, |info| info.trampoline_len.max(5));
+                busy.push(state.target + 1..state.target + prologue);
+
+                if let Some(info) = info.filter(|info| info.trampoline != 0) {
+                    busy.push(info.trampoline..info.trampoline + info.trampoline_len);
+                }
+            }
+        }
+
+        Ok((sites, busy))
+    }
+
+    unsafe fn apply(&mut self, index: usize, op: Op) -> Result<Op, Error> {
+        let hook = &mut *self.hooks[index];
+
+        match op {
+            Op::Hook(target) => hook.try_hook(target).map(|_| Op::Unhook),
+            Op::Unhook => {
+                let target = hook.info().map_or(0, |info| info.target);
+                hook.try_unhook().map(|_| Op::Hook(target))
+            }
+            Op::Toggle | Op::Enable | Op::Disable => hook.try_toggle().map(|_| Op::Toggle),
+        }
+    }
+
+    pub unsafe fn commit(mut self) -> Result<(), Error> {
+        let (sites, busy) = self.validate()?;
+
+        let mut pages = Vec::new();
+        for &site in &sites {
+            for page in [protect::page_of(site), protect::page_of(site + 4)] {
+                if !pages.contains(&page) {
+                    pages.push(page);
+                }
+            }
+        }
+
+        let mut unprotected = Vec::with_capacity(pages.len());
+        for page in pages {
+            unprotected.push(Unprotected::new(page).ok_or(Error::ProtectFailed)?);
+        }
+
+        let frozen = if sites.is_empty() {
+            None
+        } else {
+            Some(freeze::freeze()?)
+        };
+
+        if let Some(frozen) = &frozen {
+            if frozen
+                .ips()
+                .any(|ip| busy.iter().any(|range| range.contains(&ip)))
+            {
+                return Err(Error::ThreadInPatch);
+            }
+        }
+
+        let ops = mem::take(&mut self.ops);
+        let mut undo = Vec::with_capacity(ops.len());
+
+        let mut result = Ok(());
+        for &(index, op) in &ops {
+            match self.apply(index, op) {
+                Ok(inverse) => undo.push((index, inverse)),
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+
+        if result.is_err() {
+            for &(index, op) in undo.iter().rev() {
+                let _ = self.apply(index, op);
+            }
+        }
+
+        drop(frozen);
+        drop(unprotected);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{local::swap::Hook, util};
+
+    #[inline(never)]
+    fn square(x: i32) -> i32 {
+        util::black_box(x * x)
+    }
+
+    #[inline(never)]
+    fn cube(x: i32) -> i32 {
+        util::black_box(x * x * x)
+    }
+
+    fn identity(x: i32) -> i32 {
+        x
+    }
+
+    fn negate(x: i32) -> i32 {
+        -x
+    }
+
+    #[test]
+    fn commit() {
+        let mut first = unsafe { Hook::<fn(i32) -> i32>::new(identity) };
+        let mut second = unsafe { Hook::<fn(i32) -> i32>::new(negate) };
+
+        let mut transaction = Transaction::begin();
+        let a = transaction.add(&mut first);
+        let b = transaction.add(&mut second);
+        transaction
+            .hook(a, square)
+            .enable(a)
+            .hook(b, cube)
+            .enable(b);
+        unsafe { transaction.commit() }.unwrap();
+
+        assert!(first.is_enabled() && second.is_enabled());
+        assert_eq!(square(4), 4);
+        assert_eq!(cube(4), -4);
+
+        let mut transaction = Transaction::begin();
+        let a = transaction.add(&mut first);
+        let b = transaction.add(&mut second);
+        transaction.disable(a).unhook(a).disable(b).unhook(b);
+        unsafe { transaction.commit() }.unwrap();
+
+        assert!(!first.is_installed() && !second.is_installed());
+        assert_eq!(square(4), 16);
+        assert_eq!(cube(4), 64);
+    }
+
+    #[test]
+    fn rollback() {
+        let mut first = unsafe { Hook::<fn(i32) -> i32>::new(identity) };
+        let mut far = unsafe { Hook::<fn(i32) -> i32>::new(FnPtr::from_addr(0x10)) };
+
+        let mut transaction = Transaction::begin();
+        let a = transaction.add(&mut first);
+        let b = transaction.add(&mut far);
+        transaction
+            .hook(a, square)
+            .enable(a)
+            .hook(b, cube)
+            .enable(b);
+        assert_eq!(unsafe { transaction.commit() }, Err(Error::OutOfRange));
+
+        assert!(!first.is_installed() && !far.is_installed());
+        assert_eq!(square(4), 16);
+        assert_eq!(cube(4), 64);
+
+        let mut transaction = Transaction::begin();
+        let a = transaction.add(&mut first);
+        transaction.hook(a, square).disable(a);
+        assert_eq!(unsafe { transaction.commit() }, Err(Error::Disabled));
+
+        assert!(!first.is_installed());
+    }
+}
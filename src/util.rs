@@ -1,6 +1,192 @@
-#[inline(always)]
-pub unsafe fn transmute<T: Copy + 'static, U: Copy + 'static>(value: T) -> U {
-    *(&value as *const _ as *const _)
+use crate::Error;
+
+#[cfg(any(feature = "trampoline", feature = "registry"))]
+use crate::HookInfo;
+
+#[cfg(all(feature = "registry", target_os = "linux"))]
+use crate::protect::{self, Unprotected};
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+#[cfg(target_arch = "x86_64")]
+use core::arch::asm;
+
+fn splice(current: u64, start: usize, bytes: &[u8]) -> u64 {
+    let mut mask = 0;
+    let mut value = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        mask |= 0xFF << ((start + i) * 8);
+        value |= (byte as u64) << ((start + i) * 8);
+    }
+
+    current & !mask | value
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn compare_exchange_16(dest: *mut [u64; 2], current: [u64; 2], new: [u64; 2]) -> bool {
+    let success: u8;
+
+    asm!(
+        "xchg {low}, rbx",
+        "lock cmpxchg16b xmmword ptr [{dest}]",
+        "sete {success}",
+        "mov rbx, {low}",
+        dest = in(reg) dest,
+        low = inout(reg) new[0] => _,
+        success = out(reg_byte) success,
+        in("rcx") new[1],
+        inout("rax") current[0] => _,
+        inout("rdx") current[1] => _,
+        options(nostack),
+    );
+
+    success != 0
+}
+
+unsafe fn atomic(dest: *mut u8, bytes: &[u8]) -> bool {
+    let start = dest as usize;
+    let word = start & !7;
+
+    if start + bytes.len() <= word + 8 {
+        let word = &*(word as *const AtomicU64);
+
+        let mut current = word.load(Ordering::Relaxed);
+        while let Err(actual) = word.compare_exchange_weak(
+            current,
+            splice(current, start & 7, bytes),
+            Ordering::SeqCst,
+            Ordering::Relaxed,
+        ) {
+            current = actual;
+        }
+
+        return true;
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let block = start & !15;
+
+        if start + bytes.len() <= block + 16 {
+            let words = &*(block as *const [AtomicU64; 2]);
+            let split = 8 - (start & 7);
+
+            loop {
+                let current = [
+                    words[0].load(Ordering::Relaxed),
+                    words[1].load(Ordering::Relaxed),
+                ];
+                let new = [
+                    splice(current[0], start & 7, &bytes[..split]),
+                    splice(current[1], 0, &bytes[split..]),
+                ];
+
+                if compare_exchange_16(block as *mut _, current, new) {
+                    return true;
+                }
+            }
+        }
+    }
+
+    false
+}
+
+pub unsafe fn patch(dest: *mut u8, bytes: &[u8]) -> Result<(), Error> {
+    if atomic(dest, bytes) {
+        return Ok(());
+    }
+
+    if atomic(dest, &[0xEB, 0xFE]) {
+        ptr::copy_nonoverlapping(bytes[2..].as_ptr(), dest.add(2), bytes.len() - 2);
+        atomic(dest, &bytes[..2]);
+        return Ok(());
+    }
+
+    #[cfg(all(feature = "libc", target_os = "linux"))]
+    return trap::patch(dest, bytes);
+
+    #[cfg(not(all(feature = "libc", target_os = "linux")))]
+    Err(Error::Misaligned)
+}
+
+#[cfg(all(feature = "libc", target_os = "linux"))]
+mod trap {
+    use super::atomic;
+    use crate::{
+        signal::{self, Chain, REG_IP},
+        Error,
+    };
+
+    use core::{
+        hint, ptr,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    use libc::{c_int, c_void, siginfo_t, SIGTRAP, SI_KERNEL};
+
+    static CHAIN: Chain = Chain::new();
+    static LOCK: AtomicBool = AtomicBool::new(false);
+    static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn handler(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+        if (*info).si_code != SI_KERNEL {
+            if CHAIN.forward(signal, info, context) {
+                libc::raise(signal);
+            }
+
+            return;
+        }
+
+        let ip = &mut signal::registers(context)[REG_IP as usize];
+        let address = (*ip as usize).wrapping_sub(1);
+
+        if PENDING.load(Ordering::Acquire) == address
+            || ptr::read_volatile(address as *const u8) != 0xCC
+        {
+            *ip = address as _;
+            return;
+        }
+
+        if CHAIN.forward(signal, info, context) {
+            signal::registers(context)[REG_IP as usize] = address as _;
+        }
+    }
+
+    pub(super) unsafe fn patch(dest: *mut u8, bytes: &[u8]) -> Result<(), Error> {
+        CHAIN.install(SIGTRAP, handler)?;
+
+        while LOCK
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+
+        PENDING.store(dest as usize, Ordering::SeqCst);
+        atomic(dest, &[0xCC]);
+        ptr::copy_nonoverlapping(bytes[1..].as_ptr(), dest.add(1), bytes.len() - 1);
+        atomic(dest, &bytes[..1]);
+        PENDING.store(0, Ordering::SeqCst);
+
+        LOCK.store(false, Ordering::Release);
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "trampoline", feature = "registry"))]
+pub(crate) unsafe fn restore(info: &HookInfo, patched: bool) -> Result<(), Error> {
+    let bytes = if patched { info.patched } else { info.original };
+
+    #[cfg(all(feature = "registry", target_os = "linux"))]
+    let _unprotected = [
+        Unprotected::new(protect::page_of(info.target)).ok_or(Error::ProtectFailed)?,
+        Unprotected::new(protect::page_of(info.target + 4)).ok_or(Error::ProtectFailed)?,
+    ];
+
+    patch(info.target as *mut u8, &bytes)
 }
 
 #[cfg(test)]
@@ -44,6 +230,29 @@ mod test {
             );
         }
 
+        #[cfg(target_os = "linux")]
+        pub fn isolated(name: &str) -> bool {
+            extern crate std;
+
+            use std::{env, process::Command, string::String};
+
+            if env::var_os("EZHOOK_ISOLATED").is_some() {
+                return true;
+            }
+
+            let output = Command::new(env::current_exe().unwrap())
+                .args([name, "--exact", "--test-threads=1"])
+                .env("EZHOOK_ISOLATED", "1")
+                .output()
+                .unwrap();
+
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(output.status.success(), "{}", stdout);
+            assert!(stdout.contains("1 passed"), "{}", stdout);
+
+            false
+        }
+
         pub fn allocate(address: usize, size: usize) -> &'static mut [u8] {
             #[cfg(target_os = "linux")]
             let address = {
@@ -148,3 +357,98 @@ mod test {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(align(16))]
+    struct Block([u8; 32]);
+
+    #[test]
+    fn patch_words() {
+        let mut block = Block([0; 32]);
+        let base = block.0.as_mut_ptr();
+
+        unsafe { patch(base.add(1), &[1, 2, 3, 4, 5]) }.unwrap();
+        assert_eq!(block.0[..8], [0, 1, 2, 3, 4, 5, 0, 0]);
+
+        unsafe { patch(base.add(14), &[6, 7, 8, 9, 10]) }.unwrap();
+        assert_eq!(block.0[12..20], [0, 0, 6, 7, 8, 9, 10, 0]);
+    }
+
+    #[cfg(all(feature = "libc", target_os = "linux"))]
+    #[test]
+    fn patch_isolated() {
+        let mut block = Block([0; 32]);
+        let base = block.0.as_mut_ptr();
+
+        unsafe { patch(base.add(15), &[1, 2, 3, 4, 5]) }.unwrap();
+        assert_eq!(block.0[14..21], [0, 1, 2, 3, 4, 5, 0]);
+    }
+
+    #[cfg(all(feature = "libc", target_os = "linux"))]
+    #[test]
+    fn patch_running() {
+        extern crate std;
+
+        use core::{
+            mem,
+            sync::atomic::{AtomicBool, Ordering},
+        };
+        use std::{sync::Arc, thread};
+
+        if !isolated("util::tests::patch_running") {
+            return;
+        }
+
+        let code = allocate(patch_running as *const () as usize, 0x1000);
+
+        code.fill(0xCC);
+        code[64..70].copy_from_slice(&[0xB8, 2, 0, 0, 0, 0xC3]);
+
+        for offset in [14, 15] {
+            code[offset..offset + 6].copy_from_slice(&[0xB8, 1, 0, 0, 0, 0xC3]);
+
+            let function: extern "C" fn() -> u32 =
+                unsafe { mem::transmute(code.as_ptr().add(offset)) };
+            let done = Arc::new(AtomicBool::new(false));
+
+            let worker = thread::spawn({
+                let done = done.clone();
+                move || {
+                    while !done.load(Ordering::SeqCst) {
+                        assert!(matches!(function(), 1 | 2));
+                    }
+                }
+            });
+
+            let jump = (64 - (offset as i32 + 5)).to_le_bytes();
+
+            for i in 0..100000 {
+                let bytes = if i % 2 == 0 {
+                    [0xE9, jump[0], jump[1], jump[2], jump[3]]
+                } else {
+                    [0xB8, 1, 0, 0, 0]
+                };
+                unsafe { patch(code.as_mut_ptr().add(offset), &bytes) }.unwrap();
+            }
+
+            done.store(true, Ordering::SeqCst);
+            worker.join().unwrap();
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn patch_straddling() {
+        let mut block = Block([0xCC; 32]);
+        let base = block.0.as_mut_ptr();
+
+        unsafe { patch(base.add(6), &[1, 2, 3, 4, 5]) }.unwrap();
+        assert_eq!(block.0[4..13], [0xCC, 0xCC, 1, 2, 3, 4, 5, 0xCC, 0xCC]);
+
+        unsafe { patch(base.add(27), &[6, 7, 8, 9, 10]) }.unwrap();
+        assert_eq!(block.0[27..32], [6, 7, 8, 9, 10]);
+    }
+}
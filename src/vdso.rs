@@ -0,0 +1,341 @@
+use crate::{
+    local::trampoline,
+    module::Module,
+    protect::{self, Unprotected},
+    symbol::{Image, Symbol},
+    thunk, util, Error, FnPtr, HookInfo, Patch,
+};
+
+use core::ptr;
+
+use libc::{c_ulong, close, getauxval, off64_t, open, pwrite64, O_CLOEXEC, O_RDWR};
+
+#[cfg(target_pointer_width = "32")]
+use libc::Elf32_Ehdr as Ehdr;
+
+#[cfg(target_pointer_width = "64")]
+use libc::Elf64_Ehdr as Ehdr;
+
+const AT_SYSINFO_EHDR: c_ulong = 33;
+
+pub fn base() -> Option<usize> {
+    match unsafe { getauxval(AT_SYSINFO_EHDR) } {
+        0 => None,
+        base => Some(base as usize),
+    }
+}
+
+pub fn module() -> Option<Module> {
+    Module::containing(base()?)
+}
+
+pub unsafe fn image() -> Option<Image> {
+    let base = base()?;
+    let ehdr = ptr::read_unaligned(base as *const Ehdr);
+
+    let len = ehdr.e_shoff as usize + ehdr.e_shnum as usize * ehdr.e_shentsize as usize;
+
+    Some(Image::from_raw_parts(base as *const u8, len))
+}
+
+pub unsafe fn symbols(f: impl FnMut(&Symbol) -> bool) -> bool {
+    match (module(), image()) {
+        (Some(module), Some(image)) => image.symbols(module.base(), f),
+        _ => false,
+    }
+}
+
+pub unsafe fn lookup(name: &str) -> Option<usize> {
+    let mut found = None;
+
+    symbols(|symbol| {
+        let bare = symbol.name.strip_prefix(b"__vdso_").unwrap_or(symbol.name);
+
+        if symbol.is_function() && (symbol.name == name.as_bytes() || bare == name.as_bytes()) {
+            found = Some(symbol.address);
+        }
+
+        found.is_some()
+    });
+
+    found
+}
+
+unsafe fn write(address: usize, bytes: &[u8]) -> Result<(), Error> {
+    let fd = open(b"/proc/self/mem\0".as_ptr() as _, O_RDWR | O_CLOEXEC);
+    if fd < 0 {
+        return Err(Error::ProtectFailed);
+    }
+
+    let written = pwrite64(fd, bytes.as_ptr() as _, bytes.len(), address as off64_t);
+    close(fd);
+
+    if written == bytes.len() as isize {
+        Ok(())
+    } else {
+        Err(Error::ProtectFailed)
+    }
+}
+
+unsafe fn restore(info: &HookInfo, patched: bool) -> Result<(), Error> {
+    let bytes = if patched { info.patched } else { info.original };
+
+    match (
+        Unprotected::new(protect::page_of(info.target)),
+        Unprotected::new(protect::page_of(info.target + 4)),
+    ) {
+        (Some(_first), Some(_last)) => util::patch(info.target as *mut u8, &bytes),
+        _ => write(info.target, &bytes),
+    }
+}
+
+pub struct Hook<T: 'static> {
+    hook: trampoline::Hook<T>,
+    detour: T,
+    memory: usize,
+}
+
+impl<T: FnPtr> Hook<T> {
+    pub const unsafe fn new(detour: T) -> Self {
+        Self {
+            hook: trampoline::Hook::new(detour),
+            detour,
+            memory: 0,
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.hook.is_installed()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.hook.is_enabled()
+    }
+
+    pub unsafe fn try_set_detour(&mut self, detour: T) -> Result<(), Error> {
+        if self.is_installed() {
+            let relay = self.memory + trampoline::RELAY;
+
+            #[cfg(target_arch = "x86_64")]
+            util::patch(
+                (relay + 6) as *mut u8,
+                &(detour.to_addr() as u64).to_ne_bytes(),
+            )?;
+
+            #[cfg(target_arch = "x86")]
+            util::patch(
+                (relay + 1) as *mut u8,
+                &(detour.to_addr().wrapping_sub(relay + 5) as u32).to_ne_bytes(),
+            )?;
+        }
+
+        self.detour = detour;
+
+        Ok(())
+    }
+
+    pub unsafe fn set_detour(&mut self, detour: T) {
+        self.try_set_detour(detour).unwrap()
+    }
+
+    pub unsafe fn try_hook(&mut self, target: T) -> Result<(), Error> {
+        if self.is_installed() {
+            return Err(Error::AlreadyInstalled);
+        }
+
+        let target = thunk::resolve(target.to_addr());
+
+        self.hook = trampoline::Hook::new(self.detour);
+        self.hook.set_restore(restore);
+
+        self.memory = self.hook.try_hook_relayed(T::from_addr(target))?;
+
+        Ok(())
+    }
+
+    pub unsafe fn hook(&mut self, target: T) {
+        self.try_hook(target).unwrap()
+    }
+
+    pub unsafe fn try_hook_symbol(&mut self, name: &str) -> Result<(), Error> {
+        self.try_hook(T::from_addr(lookup(name).ok_or(Error::SymbolNotFound)?))
+    }
+
+    pub unsafe fn hook_symbol(&mut self, name: &str) {
+        self.try_hook_symbol(name).unwrap()
+    }
+
+    pub unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        self.hook.try_unhook()?;
+
+        self.hook.free_relayed(self.memory);
+        self.memory = 0;
+
+        Ok(())
+    }
+
+    pub unsafe fn unhook(&mut self) {
+        self.try_unhook().unwrap()
+    }
+
+    pub unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        self.hook.try_toggle()
+    }
+
+    pub unsafe fn toggle(&mut self) {
+        self.try_toggle().unwrap()
+    }
+
+    pub unsafe fn try_enable(&mut self) -> Result<(), Error> {
+        self.hook.try_enable()
+    }
+
+    pub unsafe fn enable(&mut self) {
+        self.try_enable().unwrap()
+    }
+
+    pub unsafe fn try_disable(&mut self) -> Result<(), Error> {
+        self.hook.try_disable()
+    }
+
+    pub unsafe fn disable(&mut self) {
+        self.try_disable().unwrap()
+    }
+
+    pub unsafe fn target(&self) -> T {
+        self.hook.target()
+    }
+
+    pub unsafe fn trampoline(&self) -> T {
+        self.hook.trampoline()
+    }
+
+    pub unsafe fn info(&self) -> Option<HookInfo> {
+        let mut info = self.hook.info()?;
+        info.detour = self.detour.to_addr();
+        Some(info)
+    }
+}
+
+impl<T: FnPtr> Patch for Hook<T> {
+    type Target = T;
+
+    fn is_installed(&self) -> bool {
+        Hook::is_installed(self)
+    }
+
+    fn is_enabled(&self) -> bool {
+        Hook::is_enabled(self)
+    }
+
+    unsafe fn info(&self) -> Option<HookInfo> {
+        Hook::info(self)
+    }
+
+    unsafe fn try_hook(&mut self, target: T) -> Result<(), Error> {
+        Hook::try_hook(self, target)
+    }
+
+    unsafe fn try_unhook(&mut self) -> Result<(), Error> {
+        Hook::try_unhook(self)
+    }
+
+    unsafe fn try_toggle(&mut self) -> Result<(), Error> {
+        Hook::try_toggle(self)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unnecessary_cast)]
+mod tests {
+    use super::*;
+
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use libc::{clock_gettime, clockid_t, timespec, CLOCK_MONOTONIC, CLOCK_REALTIME};
+
+    type ClockGettime = unsafe extern "C" fn(clockid_t, *mut timespec) -> i32;
+
+    static mut HOOK: Option<Hook<ClockGettime>> = None;
+    static SKEW: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn skewed(clock: clockid_t, time: *mut timespec) -> i32 {
+        let hook = (*ptr::addr_of!(HOOK)).as_ref().unwrap();
+        let result = hook.trampoline()(clock, time);
+
+        if clock == CLOCK_REALTIME {
+            (*time).tv_sec += SKEW.load(Ordering::SeqCst) as libc::time_t;
+        }
+
+        result
+    }
+
+    unsafe extern "C" fn frozen(_: clockid_t, time: *mut timespec) -> i32 {
+        (*time).tv_sec = 7;
+        (*time).tv_nsec = 0;
+        0
+    }
+
+    fn now(clock: clockid_t) -> i64 {
+        let mut time = timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        assert_eq!(unsafe { clock_gettime(clock, &mut time) }, 0);
+        time.tv_sec as i64
+    }
+
+    #[test]
+    fn lookup_symbols() {
+        let module = module().unwrap();
+        assert!(
+            module.name().starts_with(b"linux-vdso") || module.name().starts_with(b"linux-gate")
+        );
+
+        let target = unsafe { lookup("clock_gettime") }.unwrap();
+        assert_eq!(unsafe { lookup("__vdso_clock_gettime") }, Some(target));
+        assert!(module.segments().any(|segment| segment.contains(target)));
+        assert_eq!(unsafe { lookup("ezhook_missing") }, None);
+    }
+
+    #[test]
+    fn hook() {
+        SKEW.store(1_000_000, Ordering::SeqCst);
+
+        let hook = unsafe { &mut *ptr::addr_of_mut!(HOOK) };
+        *hook = Some(unsafe { Hook::new(skewed as ClockGettime) });
+        let hook = hook.as_mut().unwrap();
+
+        unsafe { hook.hook_symbol("clock_gettime") };
+
+        let real = now(CLOCK_REALTIME);
+        let monotonic = now(CLOCK_MONOTONIC);
+
+        unsafe { hook.enable() };
+
+        assert!(now(CLOCK_REALTIME) - real >= 1_000_000);
+        assert!(now(CLOCK_MONOTONIC) - monotonic < 1_000);
+
+        let target = unsafe { hook.target() } as usize;
+        let prot = unsafe { protect::protection(target) }.unwrap();
+        assert_eq!(prot & libc::PROT_WRITE, 0);
+
+        unsafe { hook.set_detour(frozen as ClockGettime) };
+        assert_eq!(now(CLOCK_REALTIME), 7);
+
+        unsafe { hook.set_detour(skewed as ClockGettime) };
+
+        let mut time = timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { hook.target()(CLOCK_REALTIME, &mut time) };
+        assert!(time.tv_sec as i64 - real >= 1_000_000);
+
+        unsafe { hook.disable() };
+
+        assert!(now(CLOCK_REALTIME) - real < 1_000);
+
+        unsafe { hook.unhook() };
+    }
+}